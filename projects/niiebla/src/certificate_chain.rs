@@ -6,7 +6,9 @@
 
 //! Implementation of the binary file format used by Nintendo to store certificate chains.
 
-use crate::signed_blob_header::{SignedBlobHeader, SignedBlobHeaderError};
+use crate::signed_blob_header::{
+    PrivateKey, SignedBlobHeader, SignedBlobHeaderError, SignedBlobHeaderVerification,
+};
 use byteorder::{BE, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Read, Seek, Write};
 use std::string::FromUtf8Error;
@@ -50,6 +52,18 @@ impl CertificateChain {
         Ok(())
     }
 
+    /// Find the certificate whose key was used to sign data with the given issuer (e.g.
+    /// `Root-CA00000001-XS00000003`), its identity will be the last element of the path and its
+    /// own issuer the rest of it.
+    pub fn issuer_certificate(&self, issuer: &str) -> Option<&Certificate> {
+        let (parent_issuer, identity) = issuer.rsplit_once('-')?;
+
+        self.certificates.iter().find(|certificate| {
+            certificate.identity == identity
+                && certificate.signed_blob_header.issuer == parent_issuer
+        })
+    }
+
     /// Get the sizes of the certificate chain in bytes.
     pub fn size(&self) -> u32 {
         self.certificates
@@ -124,6 +138,40 @@ impl Certificate {
         Ok(())
     }
 
+    /// Verify the signature of the certificate using the key of its issuer stored on the
+    /// given certificate chain. Certificates signed by the root key (`Root`) cannot be verified.
+    pub fn verify_signature(
+        &self,
+        certificate_chain: &CertificateChain,
+    ) -> Result<SignedBlobHeaderVerification, CertificateChainError> {
+        // The padding of the certificate is also signed
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+
+        Ok(self
+            .signed_blob_header
+            .verify(&signed_data, certificate_chain)?)
+    }
+
+    /// Sign the certificate with the given private key, the issuer is the path of the certificate
+    /// of the key (like `Root-CA00000001`).
+    pub fn sign_with(
+        &mut self,
+        private_key: &PrivateKey,
+        issuer: &str,
+    ) -> Result<(), CertificateChainError> {
+        self.signed_blob_header = SignedBlobHeader::new_unsigned(private_key, issuer)?;
+
+        // The padding of the certificate is also signed
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+        self.signed_blob_header.sign(&signed_data, private_key)?;
+
+        Ok(())
+    }
+
     /// Get the sizes of the certificate in bytes.
     pub fn size(&self) -> u32 {
         let size = match self.key.value {
@@ -203,3 +251,37 @@ impl CertificateKeyValue {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use std::io::Cursor;
+
+    #[test]
+    fn sign_and_verify() {
+        let certificate_chain = test_fixtures::signing_certificate_chain();
+        let mut certificate = certificate_chain.certificates[1].clone();
+
+        certificate
+            .sign_with(&test_fixtures::private_key(), "Root-CA00000001")
+            .unwrap();
+
+        assert_eq!(
+            certificate.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Authentic
+        );
+
+        // Flip a bit of the stored public key
+        let mut tampered = Cursor::new(Vec::new());
+        certificate.dump(&mut tampered).unwrap();
+        let mut tampered = tampered.into_inner();
+        tampered[0x1C8] ^= 1;
+        let tampered = Certificate::new(Cursor::new(tampered)).unwrap();
+
+        assert_eq!(
+            tampered.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Invalid
+        );
+    }
+}
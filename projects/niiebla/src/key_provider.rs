@@ -0,0 +1,451 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Implementation of the sources of the cryptographic keys used to decrypt titles.
+
+use crate::wii_common_key::{DSI_COMMON_KEY, WiiCommonKeyKind};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use thiserror::Error;
+
+/// The kinds of cryptographic keys that can be requested to a [KeyProvider].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyKind {
+    /// A common key of the Nintendo Wii, used to decrypt the title keys.
+    WiiCommon {
+        /// The kind of common key.
+        kind: WiiCommonKeyKind,
+
+        /// Either if the key is the one used on development consoles or not.
+        is_dev: bool,
+    },
+
+    /// The common key of the Nintendo DSi, used to decrypt the title keys.
+    DsiCommon {
+        /// Either if the key is the one used on development consoles or not.
+        is_dev: bool,
+    },
+
+    /// The common key of the Nintendo Wii U, used to decrypt the title keys.
+    WiiUCommon {
+        /// Either if the key is the one used on development consoles or not.
+        is_dev: bool,
+    },
+
+    /// The KeyX of the keyslot `0x3D` of the Nintendo 3DS, used to derive the common keys.
+    The3dsCommonKeyX {
+        /// Either if the key is the one used on development consoles or not.
+        is_dev: bool,
+    },
+
+    /// A KeyY of the keyslot `0x3D` of the Nintendo 3DS (given the common key index of a ticket),
+    /// used to derive the common keys.
+    The3dsCommonKeyY {
+        /// The common key index.
+        index: u8,
+
+        /// Either if the key is the one used on development consoles or not.
+        is_dev: bool,
+    },
+
+    /// The key used to encrypt the NAND of a Nintendo Wii, unique per console.
+    WiiNand,
+}
+
+impl KeyKind {
+    fn from_name(name: &str) -> Option<Self> {
+        let (name, is_dev) = match name.strip_prefix("dev_") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+
+        Some(match name {
+            "wii_common" => Self::WiiCommon {
+                kind: WiiCommonKeyKind::Normal,
+                is_dev,
+            },
+
+            "wii_korean_common" => Self::WiiCommon {
+                kind: WiiCommonKeyKind::Korean,
+                is_dev,
+            },
+
+            "wii_vwii_common" => Self::WiiCommon {
+                kind: WiiCommonKeyKind::WiiUvWii,
+                is_dev,
+            },
+
+            "dsi_common" => Self::DsiCommon { is_dev },
+            "wii_u_common" => Self::WiiUCommon { is_dev },
+
+            // Also accept the names used by other tools (`slot0x3DKeyX` and `common0` to `common5`)
+            "3ds_common_key_x" | "slot0x3DKeyX" => Self::The3dsCommonKeyX { is_dev },
+
+            "wii_nand" if !is_dev => Self::WiiNand,
+
+            name => {
+                let index = name
+                    .strip_prefix("3ds_common_key_y_")
+                    .or_else(|| name.strip_prefix("common"))?
+                    .parse()
+                    .ok()?;
+
+                Self::The3dsCommonKeyY { index, is_dev }
+            }
+        })
+    }
+}
+
+/// A source of cryptographic keys, used to decrypt (and encrypt) the contents of titles.
+pub trait KeyProvider {
+    /// Get the value of a key, `None` if it's not available.
+    fn key(&self, kind: KeyKind) -> Option<[u8; 16]>;
+}
+
+/// The keys stored inside the library: the retail and development common keys of the
+/// Nintendo Wii and the retail common key of the Nintendo DSi.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbeddedKeyProvider;
+
+impl KeyProvider for EmbeddedKeyProvider {
+    fn key(&self, kind: KeyKind) -> Option<[u8; 16]> {
+        match kind {
+            KeyKind::WiiCommon { kind, is_dev } => Some(kind.bytes_for(is_dev)),
+            KeyKind::DsiCommon { is_dev: false } => Some(DSI_COMMON_KEY),
+
+            // TODO(DISCOVER): The common key of development DSi consoles
+            KeyKind::DsiCommon { is_dev: true }
+            | KeyKind::WiiUCommon { is_dev: _ }
+            | KeyKind::The3dsCommonKeyX { is_dev: _ }
+            | KeyKind::The3dsCommonKeyY {
+                index: _,
+                is_dev: _,
+            }
+            | KeyKind::WiiNand => None,
+        }
+    }
+}
+
+/// A set of keys stored in memory, they can be inserted manually or loaded from files.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryKeyProvider {
+    /// The keys stored.
+    pub keys: HashMap<KeyKind, [u8; 16]>,
+}
+
+impl MemoryKeyProvider {
+    const WII_OTP_SIZE: usize = 0x80;
+    const WII_U_OTP_SIZE: usize = 0x400;
+    const BOOTMII_KEYS_SIZE: usize = 0x400;
+    const BOOTMII_KEYS_OTP_OFFSET: usize = 0x100;
+
+    /// Create a new empty set of keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new set of keys with all the keys stored inside the library (see
+    /// [EmbeddedKeyProvider]).
+    pub fn with_embedded_keys() -> Self {
+        let mut provider = Self::new();
+
+        for kind in [
+            WiiCommonKeyKind::Normal,
+            WiiCommonKeyKind::Korean,
+            WiiCommonKeyKind::WiiUvWii,
+        ] {
+            for is_dev in [false, true] {
+                provider.insert(KeyKind::WiiCommon { kind, is_dev }, kind.bytes_for(is_dev));
+            }
+        }
+
+        provider.insert(KeyKind::DsiCommon { is_dev: false }, DSI_COMMON_KEY);
+
+        provider
+    }
+
+    /// Insert a key, replacing the previous value if any.
+    pub fn insert(&mut self, kind: KeyKind, key: [u8; 16]) -> &mut Self {
+        self.keys.insert(kind, key);
+
+        self
+    }
+
+    /// Load the keys stored on a text file, where each line has the format `<name> = <key as
+    /// hex>`. Empty lines, lines starting with `#` and unknown names are ignored.
+    ///
+    /// The known names are `wii_common`, `wii_korean_common`, `wii_vwii_common`, `dsi_common`,
+    /// `wii_u_common`, `3ds_common_key_x`, `3ds_common_key_y_<index>` and `wii_nand`, all of them
+    /// (except `wii_nand`) can be prefixed with `dev_` for the keys of development consoles.
+    pub fn load_keys_txt<T: Read>(&mut self, mut stream: T) -> Result<&mut Self, KeyProviderError> {
+        let mut keys_txt = String::new();
+        stream.read_to_string(&mut keys_txt)?;
+
+        for (i, line) in keys_txt.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or(KeyProviderError::InvalidKeysTxtLine(i + 1))?;
+
+            let Some(kind) = KeyKind::from_name(name.trim()) else {
+                continue;
+            };
+
+            let key =
+                parse_hex_key(value.trim()).ok_or(KeyProviderError::InvalidKeysTxtLine(i + 1))?;
+            self.insert(kind, key);
+        }
+
+        Ok(self)
+    }
+
+    /// Load the keys stored on a `keys.bin` file made by
+    /// [BootMii](https://wiibrew.org/wiki/BootMii) (the OTP of a Nintendo Wii).
+    pub fn load_keys_bin<T: Read>(&mut self, mut stream: T) -> Result<&mut Self, KeyProviderError> {
+        let mut keys_bin = vec![];
+        stream.read_to_end(&mut keys_bin)?;
+
+        if keys_bin.len() != Self::BOOTMII_KEYS_SIZE {
+            return Err(KeyProviderError::InvalidFileSize(keys_bin.len()));
+        }
+
+        self.load_wii_otp(
+            &keys_bin
+                [Self::BOOTMII_KEYS_OTP_OFFSET..Self::BOOTMII_KEYS_OTP_OFFSET + Self::WII_OTP_SIZE],
+        );
+
+        Ok(self)
+    }
+
+    /// Load the keys stored on a `otp.bin` file, a dump of the OTP of a Nintendo Wii or Wii U.
+    pub fn load_otp_bin<T: Read>(&mut self, mut stream: T) -> Result<&mut Self, KeyProviderError> {
+        let mut otp = vec![];
+        stream.read_to_end(&mut otp)?;
+
+        match otp.len() {
+            Self::WII_OTP_SIZE => self.load_wii_otp(&otp),
+
+            // The first bank of the OTP of the Wii U is the one of the vWii
+            Self::WII_U_OTP_SIZE => {
+                self.load_wii_otp(&otp[..Self::WII_OTP_SIZE]);
+
+                self.insert(
+                    KeyKind::WiiCommon {
+                        kind: WiiCommonKeyKind::WiiUvWii,
+                        is_dev: false,
+                    },
+                    read_key(&otp, 0xD0),
+                );
+
+                self.insert(KeyKind::WiiUCommon { is_dev: false }, read_key(&otp, 0xE0));
+            }
+
+            len => return Err(KeyProviderError::InvalidFileSize(len)),
+        }
+
+        Ok(self)
+    }
+
+    fn load_wii_otp(&mut self, otp: &[u8]) {
+        self.insert(
+            KeyKind::WiiCommon {
+                kind: WiiCommonKeyKind::Normal,
+                is_dev: false,
+            },
+            read_key(otp, 0x14),
+        );
+
+        self.insert(KeyKind::WiiNand, read_key(otp, 0x58));
+    }
+}
+
+impl KeyProvider for MemoryKeyProvider {
+    fn key(&self, kind: KeyKind) -> Option<[u8; 16]> {
+        self.keys.get(&kind).copied()
+    }
+}
+
+fn read_key(data: &[u8], offset: usize) -> [u8; 16] {
+    let mut key = [0; 16];
+    key.copy_from_slice(&data[offset..offset + 16]);
+
+    key
+}
+
+fn parse_hex_key(value: &str) -> Option<[u8; 16]> {
+    if value.len() != 32 || !value.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut key = [0; 16];
+
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(key)
+}
+
+#[derive(Error, Debug)]
+#[allow(missing_docs)]
+pub enum KeyProviderError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Invalid line on the keys text file: {0}")]
+    InvalidKeysTxtLine(usize),
+
+    #[error("Invalid size of the keys file: {0}")]
+    InvalidFileSize(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS_TXT: &str = "\
+# Comment
+wii_common = 000102030405060708090a0b0c0d0e0f
+
+dev_wii_u_common=F0F1F2F3F4F5F6F7F8F9FAFBFCFDFEFF
+3ds_common_key_y_1 = 11111111111111111111111111111111
+unknown_key = 22222222222222222222222222222222
+dev_wii_nand = 33333333333333333333333333333333
+";
+
+    #[test]
+    fn load_keys_txt() {
+        let mut provider = MemoryKeyProvider::new();
+        provider.load_keys_txt(KEYS_TXT.as_bytes()).unwrap();
+
+        assert_eq!(provider.keys.len(), 3);
+
+        assert_eq!(
+            provider.key(KeyKind::WiiCommon {
+                kind: WiiCommonKeyKind::Normal,
+                is_dev: false,
+            }),
+            Some([
+                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
+                0x0E, 0x0F
+            ])
+        );
+
+        assert_eq!(
+            provider.key(KeyKind::WiiUCommon { is_dev: true }),
+            Some([
+                0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD,
+                0xFE, 0xFF
+            ])
+        );
+        assert_eq!(provider.key(KeyKind::WiiUCommon { is_dev: false }), None);
+
+        assert_eq!(
+            provider.key(KeyKind::The3dsCommonKeyY {
+                index: 1,
+                is_dev: false,
+            }),
+            Some([0x11; 16])
+        );
+
+        // The NAND key has no development version
+        assert_eq!(provider.key(KeyKind::WiiNand), None);
+    }
+
+    #[test]
+    fn load_keys_txt_invalid_hex() {
+        for keys_txt in [
+            "wii_common = 000102030405060708090a0b0c0d0e0g",
+            "wii_common = 000102030405060708090a0b0c0d0e",
+            "\nwii_common",
+        ] {
+            let mut provider = MemoryKeyProvider::new();
+
+            assert!(matches!(
+                provider.load_keys_txt(keys_txt.as_bytes()),
+                Err(KeyProviderError::InvalidKeysTxtLine(_))
+            ));
+        }
+
+        // The line number is reported
+        let mut provider = MemoryKeyProvider::new();
+
+        assert!(matches!(
+            provider.load_keys_txt("# Comment\n\ndsi_common = zz".as_bytes()),
+            Err(KeyProviderError::InvalidKeysTxtLine(3))
+        ));
+    }
+
+    fn wii_otp() -> Vec<u8> {
+        let mut otp = vec![0; MemoryKeyProvider::WII_OTP_SIZE];
+        otp[0x14..0x24].copy_from_slice(&[0x44; 16]);
+        otp[0x58..0x68].copy_from_slice(&[0x55; 16]);
+
+        otp
+    }
+
+    #[test]
+    fn load_keys_bin() {
+        let mut keys_bin = vec![0; MemoryKeyProvider::BOOTMII_KEYS_SIZE];
+        keys_bin[0x100..0x180].copy_from_slice(&wii_otp());
+
+        let mut provider = MemoryKeyProvider::new();
+        provider.load_keys_bin(&keys_bin[..]).unwrap();
+
+        assert_eq!(
+            provider.key(KeyKind::WiiCommon {
+                kind: WiiCommonKeyKind::Normal,
+                is_dev: false,
+            }),
+            Some([0x44; 16])
+        );
+        assert_eq!(provider.key(KeyKind::WiiNand), Some([0x55; 16]));
+
+        assert!(matches!(
+            provider.load_keys_bin(&keys_bin[..0x200]),
+            Err(KeyProviderError::InvalidFileSize(0x200))
+        ));
+    }
+
+    #[test]
+    fn load_otp_bin() {
+        let mut provider = MemoryKeyProvider::new();
+        provider.load_otp_bin(&wii_otp()[..]).unwrap();
+
+        assert_eq!(provider.key(KeyKind::WiiNand), Some([0x55; 16]));
+        assert_eq!(provider.key(KeyKind::WiiUCommon { is_dev: false }), None);
+
+        let mut wii_u_otp = vec![0; MemoryKeyProvider::WII_U_OTP_SIZE];
+        wii_u_otp[..MemoryKeyProvider::WII_OTP_SIZE].copy_from_slice(&wii_otp());
+        wii_u_otp[0xD0..0xE0].copy_from_slice(&[0x66; 16]);
+        wii_u_otp[0xE0..0xF0].copy_from_slice(&[0x77; 16]);
+
+        let mut provider = MemoryKeyProvider::new();
+        provider.load_otp_bin(&wii_u_otp[..]).unwrap();
+
+        assert_eq!(
+            provider.key(KeyKind::WiiCommon {
+                kind: WiiCommonKeyKind::WiiUvWii,
+                is_dev: false,
+            }),
+            Some([0x66; 16])
+        );
+        assert_eq!(
+            provider.key(KeyKind::WiiUCommon { is_dev: false }),
+            Some([0x77; 16])
+        );
+
+        assert!(matches!(
+            provider.load_otp_bin(&[0; 0x81][..]),
+            Err(KeyProviderError::InvalidFileSize(0x81))
+        ));
+    }
+}
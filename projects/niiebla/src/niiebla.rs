@@ -9,6 +9,7 @@
 //! [NUS (Nintendo Update Server)](https://wiibrew.org/wiki/NUS) and [iQue](https://en.wikipedia.org/wiki/IQue) platforms.
 
 pub mod certificate_chain;
+pub mod key_provider;
 pub mod signed_blob_header;
 pub mod ticket;
 pub mod title_id;
@@ -16,7 +17,11 @@ pub mod title_metadata;
 pub mod wad;
 pub mod wii_common_key;
 
+#[cfg(test)]
+mod test_fixtures;
+
 pub use certificate_chain::CertificateChain;
+pub use key_provider::{EmbeddedKeyProvider, KeyProvider};
 pub use ticket::{CryptographicMethod, PreSwitchTicket};
 pub use title_metadata::{
     TitleMetadata, TitleMetadataContentEntryKind, content_selector::ContentSelector,
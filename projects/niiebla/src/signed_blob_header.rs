@@ -6,10 +6,18 @@
 
 //! Implementation of the binary format used by Nintendo to sign files.
 
+use crate::certificate_chain::{CertificateChain, CertificateKeyValue};
 use byteorder::{BE, ReadBytesExt, WriteBytesExt};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
 use std::boxed::Box;
-use std::io::{self, Read, Seek, Write};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::num::NonZeroUsize;
 use std::string::{FromUtf8Error, String};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
 use thiserror::Error;
 use util::{StreamPin, WriteEx};
 
@@ -24,6 +32,10 @@ pub struct SignedBlobHeader {
 }
 
 impl SignedBlobHeader {
+    /// Certificate Authorities (CA) used to sign data for development consoles, `CA00000002` on
+    /// the Wii and `CA00000004` on the Wii U and 3DS.
+    const DEVELOPMENT_CERTIFICATE_AUTHORITIES: [&str; 2] = ["CA00000002", "CA00000004"];
+
     /// Create a new [SignedBlobHeader] by parsing an stream.
     pub fn new<T: Read + Seek>(stream: T) -> Result<Self, SignedBlobHeaderError> {
         let mut stream = StreamPin::new(stream)?;
@@ -64,6 +76,322 @@ impl SignedBlobHeader {
 
         util::align_to_boundary(size, 64) as u32
     }
+
+    /// Either if the data was signed for development consoles or not, given the Certificate
+    /// Authority on its issuer (like `Root-CA00000002-XS00000006`).
+    pub fn is_dev(&self) -> bool {
+        let mut issuer = self.issuer.split('-');
+
+        issuer.next() == Some("Root")
+            && issuer.next().is_some_and(|certificate_authority| {
+                Self::DEVELOPMENT_CERTIFICATE_AUTHORITIES.contains(&certificate_authority)
+            })
+    }
+
+    /// Offset (relative to the start of the header) where the signed data starts, the signature
+    /// only covers the data from the issuer onwards.
+    pub(crate) fn signed_data_offset(&self) -> usize {
+        self.size() as usize - 64
+    }
+
+    /// Get the data covered by the signature of a signed blob (see [Self::signed_data_offset]),
+    /// the whole blob is dumped into memory with the given function and padded with zeroes up to
+    /// the given size.
+    pub(crate) fn signed_data(
+        &self,
+        size: usize,
+        dump: impl FnOnce(&mut Cursor<Vec<u8>>) -> io::Result<()>,
+    ) -> io::Result<Vec<u8>> {
+        let mut blob = Cursor::new(Vec::new());
+        dump(&mut blob)?;
+
+        let mut blob = blob.into_inner();
+        blob.resize(blob.len().max(size), 0);
+
+        Ok(blob.split_off(self.signed_data_offset()))
+    }
+
+    /// Create a header with a zeroed signature of the kind made by the given private key, it must
+    /// be signed with [Self::sign] after dumping the data that will be signed.
+    pub(crate) fn new_unsigned(
+        private_key: &PrivateKey,
+        issuer: &str,
+    ) -> Result<Self, SignedBlobHeaderError> {
+        if issuer.len() > 64 {
+            return Err(SignedBlobHeaderError::IssuerTooLong(issuer.to_string()));
+        }
+
+        let signature = match (private_key.key.size(), private_key.hash) {
+            (512, PrivateKeyHash::Sha1) => {
+                SignedBlobHeaderSignature::Rsa4096Sha1(Box::new([0; 512]))
+            }
+            (512, PrivateKeyHash::Sha256) => {
+                SignedBlobHeaderSignature::Rsa4096Sha256(Box::new([0; 512]))
+            }
+            (256, PrivateKeyHash::Sha1) => {
+                SignedBlobHeaderSignature::Rsa2048Sha1(Box::new([0; 256]))
+            }
+            (256, PrivateKeyHash::Sha256) => {
+                SignedBlobHeaderSignature::Rsa2048Sha256(Box::new([0; 256]))
+            }
+
+            (size, _) => return Err(SignedBlobHeaderError::UnsupportedPrivateKeySize(size)),
+        };
+
+        Ok(Self {
+            signature,
+            issuer: issuer.to_string(),
+        })
+    }
+
+    /// Sign the given signed data (starting from the issuer), the header must have been created
+    /// with [Self::new_unsigned] using the same private key.
+    pub(crate) fn sign(
+        &mut self,
+        signed_data: &[u8],
+        private_key: &PrivateKey,
+    ) -> Result<(), SignedBlobHeaderError> {
+        let (hash, padding) = match private_key.hash {
+            PrivateKeyHash::Sha1 => (
+                Sha1::digest(signed_data).to_vec(),
+                Pkcs1v15Sign::new::<Sha1>(),
+            ),
+
+            PrivateKeyHash::Sha256 => (
+                Sha256::digest(signed_data).to_vec(),
+                Pkcs1v15Sign::new::<Sha256>(),
+            ),
+        };
+
+        let new_signature = private_key.key.sign(padding, &hash)?;
+
+        match &mut self.signature {
+            SignedBlobHeaderSignature::Rsa4096Sha1(signature)
+            | SignedBlobHeaderSignature::Rsa4096Sha256(signature) => {
+                signature.copy_from_slice(&new_signature)
+            }
+
+            SignedBlobHeaderSignature::Rsa2048Sha1(signature)
+            | SignedBlobHeaderSignature::Rsa2048Sha256(signature) => {
+                signature.copy_from_slice(&new_signature)
+            }
+
+            SignedBlobHeaderSignature::EcdsaSha1(_)
+            | SignedBlobHeaderSignature::EcdsaSha256(_)
+            | SignedBlobHeaderSignature::HmacSha1(_) => {
+                return Err(SignedBlobHeaderError::UnverifiableSignatureKind());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify the signature over the given signed data (starting from the issuer) using the key
+    /// of the issuer certificate stored on the certificate chain.
+    pub(crate) fn verify(
+        &self,
+        signed_data: &[u8],
+        certificate_chain: &CertificateChain,
+    ) -> Result<SignedBlobHeaderVerification, SignedBlobHeaderError> {
+        let (signature, hash, padding, is_sha1) = match &self.signature {
+            SignedBlobHeaderSignature::Rsa4096Sha1(signature) => (
+                signature.as_slice(),
+                Sha1::digest(signed_data).to_vec(),
+                Pkcs1v15Sign::new::<Sha1>(),
+                true,
+            ),
+
+            SignedBlobHeaderSignature::Rsa2048Sha1(signature) => (
+                signature.as_slice(),
+                Sha1::digest(signed_data).to_vec(),
+                Pkcs1v15Sign::new::<Sha1>(),
+                true,
+            ),
+
+            SignedBlobHeaderSignature::Rsa4096Sha256(signature) => (
+                signature.as_slice(),
+                Sha256::digest(signed_data).to_vec(),
+                Pkcs1v15Sign::new::<Sha256>(),
+                false,
+            ),
+
+            SignedBlobHeaderSignature::Rsa2048Sha256(signature) => (
+                signature.as_slice(),
+                Sha256::digest(signed_data).to_vec(),
+                Pkcs1v15Sign::new::<Sha256>(),
+                false,
+            ),
+
+            SignedBlobHeaderSignature::EcdsaSha1(_)
+            | SignedBlobHeaderSignature::EcdsaSha256(_)
+            | SignedBlobHeaderSignature::HmacSha1(_) => {
+                return Err(SignedBlobHeaderError::UnverifiableSignatureKind());
+            }
+        };
+
+        // The "Trucha bug" only compares the SHA-1 hash as a string, stopping on the first
+        // zero byte
+        if signature.iter().all(|byte| *byte == 0) {
+            return Ok(if is_sha1 && hash[0] == 0 {
+                SignedBlobHeaderVerification::Fakesigned
+            } else {
+                SignedBlobHeaderVerification::Invalid
+            });
+        }
+
+        let certificate = certificate_chain
+            .issuer_certificate(&self.issuer)
+            .ok_or_else(|| SignedBlobHeaderError::IssuerNotFound(self.issuer.clone()))?;
+
+        let (modulus, public_exponent) = match &certificate.key.value {
+            CertificateKeyValue::Rsa4096(value) => value.split_at(512),
+            CertificateKeyValue::Rsa2048(value) => value.split_at(256),
+            CertificateKeyValue::EccB223(_) => return Ok(SignedBlobHeaderVerification::Invalid),
+        };
+
+        let Ok(public_key) = RsaPublicKey::new(
+            BigUint::from_bytes_be(modulus),
+            BigUint::from_bytes_be(public_exponent),
+        ) else {
+            return Ok(SignedBlobHeaderVerification::Invalid);
+        };
+
+        Ok(match public_key.verify(padding, &hash, signature) {
+            Ok(()) => SignedBlobHeaderVerification::Authentic,
+            Err(_) => SignedBlobHeaderVerification::Invalid,
+        })
+    }
+}
+
+/// A RSA-2048 or RSA-4096 private key used to sign data, only RSA signatures can be made.
+pub struct PrivateKey {
+    key: RsaPrivateKey,
+    hash: PrivateKeyHash,
+}
+
+impl PrivateKey {
+    /// Create a new private key from its modulus and public and private exponents (as big endian
+    /// integers), the signatures made with it will use the given hash algorithm.
+    pub fn from_rsa_components(
+        modulus: &[u8],
+        public_exponent: &[u8],
+        private_exponent: &[u8],
+        hash: PrivateKeyHash,
+    ) -> Result<Self, SignedBlobHeaderError> {
+        let key = RsaPrivateKey::from_components(
+            BigUint::from_bytes_be(modulus),
+            BigUint::from_bytes_be(public_exponent),
+            BigUint::from_bytes_be(private_exponent),
+            vec![],
+        )?;
+
+        match key.size() {
+            256 | 512 => Ok(Self { key, hash }),
+            size => Err(SignedBlobHeaderError::UnsupportedPrivateKeySize(size)),
+        }
+    }
+
+    /// Get the public key to be stored on a [Certificate](crate::certificate_chain::Certificate),
+    /// useful to create a custom chain of trust.
+    pub fn public_key_value(&self) -> CertificateKeyValue {
+        let modulus = self.key.n().to_bytes_be();
+        let public_exponent = self.key.e().to_bytes_be();
+
+        // Both values are stored as padded big endian integers
+        let mut value = vec![0; self.key.size() + 4];
+        let modulus_end = self.key.size();
+        value[modulus_end - modulus.len()..modulus_end].copy_from_slice(&modulus);
+        value[modulus_end + 4 - public_exponent.len()..].copy_from_slice(&public_exponent);
+
+        #[allow(clippy::expect_used)]
+        match self.key.size() {
+            512 => CertificateKeyValue::Rsa4096(Box::new(
+                value
+                    .try_into()
+                    .expect("This will never panic as the size of the key has been checked"),
+            )),
+
+            _ => CertificateKeyValue::Rsa2048(Box::new(
+                value
+                    .try_into()
+                    .expect("This will never panic as the size of the key has been checked"),
+            )),
+        }
+    }
+}
+
+/// The hash algorithm used by a [PrivateKey] to make its signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivateKeyHash {
+    /// SHA-1, used on the Nintendo Wii, DSi and 3DS.
+    Sha1,
+
+    /// SHA-256, used on the Nintendo Wii U and 3DS.
+    Sha256,
+}
+
+/// The result of verifying the signature of a signed blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignedBlobHeaderVerification {
+    /// The signature was made with the private key of the issuer.
+    Authentic,
+
+    /// The signature is zeroed and the SHA-1 hash of the signed data starts with a zero byte,
+    /// only accepted by systems affected by the
+    /// ["Trucha bug"](https://wiibrew.org/wiki/Signing_bug).
+    Fakesigned,
+
+    /// The signature does not match the signed data.
+    Invalid,
+}
+
+/// Brute-force a big endian [u16] field stored at the given offset of some signed data until
+/// its SHA-1 hash starts with a zero byte, this is enough to exploit the
+/// ["Trucha bug"](https://wiibrew.org/wiki/Signing_bug) present on old versions of the IOS
+/// given a zeroed signature. The search can be split between multiple threads.
+///
+/// The result will always be the lowest valid value, no matter the number of threads.
+pub(crate) fn trucha_brute_force(
+    signed_data: &[u8],
+    field_offset: usize,
+    number_of_threads: NonZeroUsize,
+) -> Result<u16, SignedBlobHeaderError> {
+    const NUMBER_OF_VALUES: usize = u16::MAX as usize + 1;
+
+    let found_value = AtomicU32::new(u32::MAX);
+    let values_per_thread = NUMBER_OF_VALUES.div_ceil(number_of_threads.get());
+
+    thread::scope(|scope| {
+        for i in 0..number_of_threads.get() {
+            let found_value = &found_value;
+            let mut signed_data = signed_data.to_vec();
+
+            scope.spawn(move || {
+                let start = i * values_per_thread;
+                let end = NUMBER_OF_VALUES.min(start + values_per_thread);
+
+                for value in start..end {
+                    // A lower value has been already found by other thread
+                    if value as u32 > found_value.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    signed_data[field_offset..field_offset + 2]
+                        .copy_from_slice(&(value as u16).to_be_bytes());
+
+                    if Sha1::digest(&signed_data)[0] == 0 {
+                        found_value.fetch_min(value as u32, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    match found_value.into_inner() {
+        u32::MAX => Err(SignedBlobHeaderError::FakesignValueNotFound()),
+        value => Ok(value as u16),
+    }
 }
 
 #[derive(Error, Debug)]
@@ -77,6 +405,24 @@ pub enum SignedBlobHeaderError {
 
     #[error("UTF-8 error: {0}")]
     Utf8Error(#[from] FromUtf8Error),
+
+    #[error("No value of the field makes the hash of the signed data valid for fakesigning")]
+    FakesignValueNotFound(),
+
+    #[error("Unable to find the certificate of the issuer on the certificate chain: {0}")]
+    IssuerNotFound(String),
+
+    #[error("Only RSA signatures can be verified or made")]
+    UnverifiableSignatureKind(),
+
+    #[error("The issuer is longer than 64 bytes: {0}")]
+    IssuerTooLong(String),
+
+    #[error("Only RSA-2048 and RSA-4096 private keys are supported (key size: {0} bytes)")]
+    UnsupportedPrivateKeySize(usize),
+
+    #[error("RSA error: {0}")]
+    RsaError(#[from] rsa::Error),
 }
 
 /// Signature in different cryptography formats.
@@ -105,6 +451,16 @@ pub enum SignedBlobHeaderSignature {
 }
 
 impl SignedBlobHeaderSignature {
+    /// Replace all the bytes of the signature with zeroes, keeping its kind.
+    pub fn clear(&mut self) {
+        match self {
+            Self::Rsa4096Sha1(data) | Self::Rsa4096Sha256(data) => data.fill(0),
+            Self::Rsa2048Sha1(data) | Self::Rsa2048Sha256(data) => data.fill(0),
+            Self::EcdsaSha1(data) | Self::EcdsaSha256(data) => data.fill(0),
+            Self::HmacSha1(data) => data.fill(0),
+        }
+    }
+
     fn new<T: Read>(mut stream: T) -> Result<Self, SignedBlobHeaderError> {
         Ok(match stream.read_u32::<BE>()? {
             0x010000 => {
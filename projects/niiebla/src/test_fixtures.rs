@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Raw binary data shared by the tests of the crate.
+
+use crate::certificate_chain::CertificateChain;
+use crate::certificate_chain::{Certificate, CertificateKey, CertificateKeyValue};
+use crate::signed_blob_header::{
+    PrivateKey, PrivateKeyHash, SignedBlobHeader, SignedBlobHeaderSignature,
+};
+use crate::wad::installable::{WadBuilder, WadBuilderContent};
+use crate::{
+    CryptographicMethod, EmbeddedKeyProvider, PreSwitchTicket, TitleMetadata,
+    TitleMetadataContentEntryKind,
+};
+use std::io::Cursor;
+
+/// The title ID used by the fixtures ("HAGA").
+pub(crate) const TITLE_ID: u64 = 0x0001_0001_4841_4741;
+
+fn signed_blob_header(issuer: &str, signature_filler: u8) -> Vec<u8> {
+    let mut bytes = vec![];
+
+    // RSA-2048 with SHA-1
+    bytes.extend_from_slice(&0x0001_0001_u32.to_be_bytes());
+    bytes.extend_from_slice(&[signature_filler; 256]);
+    bytes.extend_from_slice(&[0; 60]);
+
+    let mut issuer = issuer.as_bytes().to_vec();
+    issuer.resize(64, 0);
+    bytes.extend_from_slice(&issuer);
+
+    bytes
+}
+
+/// A certificate chain with the same certificates as the ones stored on Wii WADs (with dummy
+/// keys and signatures).
+pub(crate) fn certificate_chain() -> CertificateChain {
+    let certificate = |issuer: &str, identity: &str| Certificate {
+        signed_blob_header: SignedBlobHeader {
+            signature: SignedBlobHeaderSignature::Rsa2048Sha1(Box::new([0x11; 256])),
+            issuer: issuer.to_string(),
+        },
+        identity: identity.to_string(),
+        key: CertificateKey {
+            id: 7,
+            value: CertificateKeyValue::Rsa2048(Box::new([0x22; 256 + 4])),
+        },
+    };
+
+    CertificateChain {
+        certificates: vec![
+            certificate("Root", "CA00000001"),
+            certificate("Root-CA00000001", "XS00000003"),
+            certificate("Root-CA00000001", "CP00000004"),
+        ],
+    }
+}
+
+/// Modulus of a RSA-2048 key made only for the tests (`openssl genrsa 2048`).
+const PRIVATE_KEY_MODULUS: &str = concat!(
+    "8e529e667f6123bf33cb35d10c34b9d1cf40d2a4b06bc47a5244d0047a980528d0c170b91908af6f",
+    "5e5722d0409bc17509d103300cb23bd6ef8ed7cc3975e3452ce5eaa2e0c7fe27acc64c40799547f3",
+    "1925c6709b2bedaa2318f922048e1600771719068b933ed6562e717609d00cc056500b301dd450a8",
+    "e9e64ce83824647287f5eebc477f9a74b0694ff086337444beb5e58775848b27466cdfa71b8e66f9",
+    "4236dbcf9dea6676a9d791e3796989bb1d5a02919188367e3c0ba75a292dca22f8ba491d70fcc0c3",
+    "df7a7892833693aa58dd3423cf22b41857d57340392b1ad846a1d9aa141ffb05c9ac591329cd92c5",
+    "f9acda835c1a8cb0e9d932c515da0f51",
+);
+
+/// Private exponent of the RSA-2048 key made only for the tests.
+const PRIVATE_KEY_PRIVATE_EXPONENT: &str = concat!(
+    "02b0a5a938e0b671a184f13695d92f1c1bce1a7a787cada27a68adf8b05e01a14abd25eda909d8b2",
+    "0824696655059f4ceb0e1f56029ec1a234d5dc4c3cd7a14a458dda1c5bc20a9d5d46eeba4714b19e",
+    "fd9e2634eae93ec533ae5ff2c5b204a65bd9e2342a821d180fef31b50f7cab945cea9ca430a37a90",
+    "fa1720a3eb80119770340b141d7057926b4dc1ee31996cadb589d0f8a27810802e61e03a62a0de27",
+    "19c5a53b7992ffedaff2d40a34ad92e1e10183fb3f660396f0cc1f0004fb5181a01357ab39265dbc",
+    "eb005cf7406918c01ba0e1d6f5c6d1d19c3fc627588d6ac8a7ea9a866ce2d2063e27a63dd9867bbf",
+    "0b2d444de05b3a50de17ea28592e78f9",
+);
+
+fn hex_to_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// A RSA-2048 private key that makes SHA-1 signatures.
+pub(crate) fn private_key() -> PrivateKey {
+    PrivateKey::from_rsa_components(
+        &hex_to_bytes(PRIVATE_KEY_MODULUS),
+        &0x0001_0001_u32.to_be_bytes(),
+        &hex_to_bytes(PRIVATE_KEY_PRIVATE_EXPONENT),
+        PrivateKeyHash::Sha1,
+    )
+    .unwrap()
+}
+
+/// Like [certificate_chain] but all the certificates hold the public key of [private_key].
+pub(crate) fn signing_certificate_chain() -> CertificateChain {
+    let mut certificate_chain = certificate_chain();
+    let key_value = private_key().public_key_value();
+
+    for certificate in &mut certificate_chain.certificates {
+        certificate.key.value = key_value.clone();
+    }
+
+    certificate_chain
+}
+
+/// Raw bytes of a Wii ticket (version 0) that is not device unique.
+pub(crate) fn ticket_bytes() -> Vec<u8> {
+    let mut bytes = signed_blob_header("Root-CA00000001-XS00000003", 0x33);
+
+    // ECC public key
+    bytes.extend_from_slice(&[0; 60]);
+    // Version
+    bytes.push(0);
+    // Reserved
+    bytes.extend_from_slice(&[0; 2]);
+    // Encrypted title key
+    bytes.extend_from_slice(&[0x5A; 16]);
+    // Reserved
+    bytes.push(0);
+    // Ticket ID
+    bytes.extend_from_slice(&0x0102_0304_0506_0708_u64.to_be_bytes());
+    // Console ID
+    bytes.extend_from_slice(&0_u32.to_be_bytes());
+    bytes.extend_from_slice(&TITLE_ID.to_be_bytes());
+    // System access mask
+    bytes.extend_from_slice(&0xFFFF_u16.to_be_bytes());
+    // Title version
+    bytes.extend_from_slice(&3_u16.to_be_bytes());
+    // Permitted titles mask and permit mask
+    bytes.extend_from_slice(&0_u32.to_be_bytes());
+    bytes.extend_from_slice(&0_u32.to_be_bytes());
+    // License type
+    bytes.push(0);
+    // Common key index
+    bytes.push(0);
+    // Unknown
+    bytes.extend_from_slice(&[0; 47]);
+    // Audit
+    bytes.push(0);
+    // Content access flags
+    bytes.extend_from_slice(&[0xFF; 64]);
+    // Padding
+    bytes.extend_from_slice(&[0; 2]);
+    // Limit entries
+    bytes.extend_from_slice(&[0; 64]);
+
+    bytes
+}
+
+/// Raw bytes of a Wii title metadata (version 0) with a normal content entry of each given
+/// size, the ID and index of each content is its position.
+pub(crate) fn title_metadata_bytes(content_sizes: &[u64]) -> Vec<u8> {
+    let mut bytes = signed_blob_header("Root-CA00000001-CP00000004", 0x44);
+
+    // Version, CA CRL version, signer CRL version and vWii flag
+    bytes.extend_from_slice(&[0; 4]);
+    // System runtime title ID (IOS58)
+    bytes.extend_from_slice(&0x0000_0001_0000_003A_u64.to_be_bytes());
+    bytes.extend_from_slice(&TITLE_ID.to_be_bytes());
+    // Title type
+    bytes.extend_from_slice(&1_u32.to_be_bytes());
+    // Group ID
+    bytes.extend_from_slice(&0_u16.to_be_bytes());
+    // Platform data: padding, region (free) and ratings
+    bytes.extend_from_slice(&[0; 2]);
+    bytes.extend_from_slice(&3_u16.to_be_bytes());
+    bytes.extend_from_slice(&[0x80; 16]);
+    // Platform data: reserved, IPC mask and reserved
+    bytes.extend_from_slice(&[0; 12]);
+    bytes.extend_from_slice(&[0; 12]);
+    bytes.extend_from_slice(&[0; 18]);
+    // Access rights
+    bytes.extend_from_slice(&0_u32.to_be_bytes());
+    // Title version
+    bytes.extend_from_slice(&3_u16.to_be_bytes());
+
+    let number_of_contents = content_sizes.len() as u16;
+    bytes.extend_from_slice(&number_of_contents.to_be_bytes());
+    // Boot content index
+    bytes.extend_from_slice(&0_u16.to_be_bytes());
+    // Minor version
+    bytes.extend_from_slice(&0_u16.to_be_bytes());
+
+    for (i, size) in content_sizes.iter().enumerate() {
+        bytes.extend_from_slice(&(i as u32).to_be_bytes());
+        bytes.extend_from_slice(&(i as u16).to_be_bytes());
+        // Normal content
+        bytes.extend_from_slice(&1_u16.to_be_bytes());
+        bytes.extend_from_slice(&size.to_be_bytes());
+        bytes.extend_from_slice(&[0; 20]);
+    }
+
+    bytes
+}
+
+/// Like [title_metadata_bytes] but with the V1 extension (zeroed content entries groups) and
+/// SHA-256 hashes on the content entries.
+pub(crate) fn title_metadata_v1_bytes(content_sizes: &[u64]) -> Vec<u8> {
+    // Size of the data before the V1 extension
+    const HEADER_SIZE: usize = 0x1E4;
+
+    let version_0 = title_metadata_bytes(content_sizes);
+    let mut bytes = version_0[..HEADER_SIZE].to_vec();
+
+    // Version
+    bytes[0x180] = 1;
+
+    // Hash of the content entries groups and the groups themselves
+    bytes.extend_from_slice(&[0; 32]);
+    bytes.extend_from_slice(&[0; 64 * 36]);
+
+    for entry in version_0[HEADER_SIZE..].chunks(36) {
+        bytes.extend_from_slice(entry);
+        bytes.extend_from_slice(&[0; 12]);
+    }
+
+    bytes
+}
+
+/// Raw bytes of an installable WAD made with [ticket_bytes] and [title_metadata_bytes], with two
+/// contents (of 0x25 and 0x40 bytes) and the given footer.
+pub(crate) fn installable_wad_bytes(footer: &[u8]) -> Vec<u8> {
+    let certificate_chain = certificate_chain();
+    let ticket = PreSwitchTicket::new(Cursor::new(ticket_bytes())).unwrap();
+    let mut title_metadata = TitleMetadata::new(Cursor::new(title_metadata_bytes(&[]))).unwrap();
+
+    let content_0 = installable_wad_content(0);
+    let content_1 = installable_wad_content(1);
+
+    let mut stream = Cursor::new(vec![]);
+    let mut builder = WadBuilder::new(
+        &certificate_chain,
+        &ticket,
+        &mut title_metadata,
+        CryptographicMethod::Wii,
+        &EmbeddedKeyProvider,
+    );
+
+    builder
+        .add_content(
+            0x10,
+            0,
+            TitleMetadataContentEntryKind::Normal,
+            WadBuilderContent::Decrypted(Box::new(&content_0[..])),
+        )
+        .add_content(
+            0x20,
+            1,
+            TitleMetadataContentEntryKind::Shared,
+            WadBuilderContent::Decrypted(Box::new(&content_1[..])),
+        );
+
+    if !footer.is_empty() {
+        builder.set_footer(footer);
+    }
+
+    builder.build(&mut stream).unwrap();
+
+    stream.into_inner()
+}
+
+/// The decrypted data of a content (given its index) of [installable_wad_bytes].
+pub(crate) fn installable_wad_content(index: u16) -> Vec<u8> {
+    match index {
+        0 => (0..0x25).collect(),
+        _ => vec![0xCD; 0x40],
+    }
+}
@@ -6,24 +6,33 @@
 
 //! Implementation of the binary file format used by Nintendo to store tickets.
 
+use crate::CertificateChain;
 use crate::ContentSelector;
 use crate::TitleMetadata;
-use crate::signed_blob_header::{SignedBlobHeader, SignedBlobHeaderError};
+use crate::key_provider::{KeyKind, KeyProvider};
+use crate::signed_blob_header::{
+    self, PrivateKey, SignedBlobHeader, SignedBlobHeaderError, SignedBlobHeaderVerification,
+};
 use crate::title_id::TitleId;
 use crate::title_metadata::TitleMetadataError;
-use crate::wii_common_key::{CommonKeyKindError, WiiCommonKeyKind};
-use aes::cipher::{BlockDecryptMut, KeyIvInit, block_padding::NoPadding};
+use crate::wii_common_key::{self, CommonKeyKindError, WiiCommonKeyKind};
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::NoPadding};
 use bitflags::bitflags;
 use byteorder::{BE, ReadBytesExt, WriteBytesExt};
 use std::io;
 use std::io::Read;
 use std::io::{Seek, Write};
+use std::num::NonZeroUsize;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 use util::Aes128CbcDec;
+use util::Aes128CbcEnc;
 use util::AesCbcStream;
 use util::WriteEx;
 
+use hashed_content::HashedContentStream;
+
+pub mod hashed_content;
 pub mod v1;
 
 /// The different cryptographic methods that can be used to decrypt the content stored inside a
@@ -32,6 +41,21 @@ pub mod v1;
 pub enum CryptographicMethod {
     /// The method used in the Nintendo Wii (and Wii U vWii) platform.
     Wii,
+
+    /// The method used in the Nintendo DSi platform (DSiWare TAD files), the same as the
+    /// Nintendo Wii but with its own common key.
+    Dsi,
+
+    /// The method used in the Nintendo Wii U platform, its common key is not stored in the
+    /// library. Hashed contents must be decrypted with
+    /// [PreSwitchTicket::hashed_cryptographic_stream].
+    WiiU,
+
+    /// The method used in the Nintendo 3DS platform (CIA files), the common key is derived by the
+    /// key scrambler (see [wii_common_key::key_scrambler_3ds]) from the KeyX of the keyslot `0x3D`
+    /// and the KeyY selected by the common key index of the ticket, both are not stored in the
+    /// library.
+    The3ds,
 }
 
 /// Manifest data regard the ownership of a title and its permissions over the hardware.
@@ -64,6 +88,9 @@ pub struct PreSwitchTicket {
     /// is used to encrypt the title content.
     pub encrypted_title_key: [u8; 16],
 
+    /// Reserved byte, kept as is to dump the ticket without changes.
+    pub reserved: u8,
+
     /// The ID of the ticket.
     pub ticket_id: u64,
 
@@ -99,6 +126,10 @@ pub struct PreSwitchTicket {
     /// common key is platform dependant.
     pub common_key_kind_index: u8,
 
+    /// Bytes whose use is still unknown, kept as is to dump the ticket without changes.
+    // TODO(DISCOVER)
+    pub unknown_data: [u8; 47],
+
     /// Audit or revision of the title. The meaning is still not clear.
     // TODO(DISCOVER)
     pub audit: u8,
@@ -107,6 +138,10 @@ pub struct PreSwitchTicket {
     // TODO(DISCOVER)
     pub content_access_permissions: [u8; 64],
 
+    /// Padding bytes without any meaning, usually the value changed while fakesigning (see
+    /// [Self::fakesign]).
+    pub padding: u16,
+
     /// A set of limits over the use of the title.
     pub limit_entries: [PreSwitchTicketLimitEntry; 8],
 
@@ -115,6 +150,9 @@ pub struct PreSwitchTicket {
 }
 
 impl PreSwitchTicket {
+    /// Offset of [Self::padding] inside the signed data.
+    const FAKESIGN_FIELD_OFFSET: usize = 290;
+
     /// Parse a ticket.
     pub fn new<T: Read + Seek>(mut stream: T) -> Result<Self, PreSwitchTicketError> {
         let signed_blob_header = SignedBlobHeader::new(&mut stream)?;
@@ -127,8 +165,7 @@ impl PreSwitchTicket {
 
         let encrypted_title_key = util::read_exact!(stream, 16)?;
 
-        // Skip 1 reserved byte
-        stream.seek_relative(1)?;
+        let reserved = stream.read_u8()?;
 
         let ticket_id = stream.read_u64::<BE>()?;
 
@@ -152,14 +189,12 @@ impl PreSwitchTicket {
         let license = PreTicketLicense::new(stream.read_u8()?)?;
         let common_key_kind_index = stream.read_u8()?;
 
-        // Skip 47 byte whose use is still unknown
-        stream.seek_relative(47)?;
+        let unknown_data = util::read_exact!(stream, 47)?;
 
         let audit = stream.read_u8()?;
         let content_access_permissions = util::read_exact!(stream, 64)?;
 
-        // Skip padding of 2 bytes
-        stream.seek_relative(2)?;
+        let padding = stream.read_u16::<BE>()?;
 
         let mut limit_entries = [const { PreSwitchTicketLimitEntry::NoLimit { kind: 0 } }; 8];
         for limit_entry in &mut limit_entries {
@@ -184,6 +219,7 @@ impl PreSwitchTicket {
             certificate_authority_certificate_revocation_list_version,
             signer_certificate_revocation_list_version,
             encrypted_title_key,
+            reserved,
             ticket_id,
             device_id,
             title_id,
@@ -193,8 +229,10 @@ impl PreSwitchTicket {
             permitted_generic_title_id_mask,
             license,
             common_key_kind_index,
+            unknown_data,
             audit,
             content_access_permissions,
+            padding,
             limit_entries,
             version_1_extension,
         })
@@ -209,8 +247,7 @@ impl PreSwitchTicket {
         stream.write_u8(self.signer_certificate_revocation_list_version)?;
         stream.write_all(&self.encrypted_title_key)?;
 
-        // Skip 1 reserved byte
-        stream.write_zeroed(1)?;
+        stream.write_u8(self.reserved)?;
 
         stream.write_u64::<BE>(self.ticket_id)?;
         stream.write_u32::<BE>(self.device_id.unwrap_or(0))?;
@@ -222,14 +259,12 @@ impl PreSwitchTicket {
         self.license.dump(&mut stream)?;
         stream.write_u8(self.common_key_kind_index)?;
 
-        // Skip 47 assigned but unused bytes
-        stream.write_zeroed(47)?;
+        stream.write_all(&self.unknown_data)?;
 
         stream.write_u8(self.audit)?;
         stream.write_all(&self.content_access_permissions)?;
 
-        // Skip 2 bytes of padding
-        stream.write_zeroed(2)?;
+        stream.write_u16::<BE>(self.padding)?;
 
         for limit_entry in &self.limit_entries {
             limit_entry.dump(&mut stream)?;
@@ -253,67 +288,285 @@ impl PreSwitchTicket {
         size
     }
 
+    /// Fakesign the ticket: its signature will be zeroed and [Self::padding] will be changed
+    /// until the SHA-1 hash of the signed data starts with a zero byte, making it pass the
+    /// signature check on systems affected by the
+    /// ["Trucha bug"](https://wiibrew.org/wiki/Signing_bug).
+    ///
+    /// The ticket must be fakesigned again after any modification.
+    pub fn fakesign(&mut self) -> Result<(), PreSwitchTicketError> {
+        self.fakesign_parallel(NonZeroUsize::MIN)
+    }
+
+    /// Like [Self::fakesign] but the search will be split between the given number of threads,
+    /// the result will be the same.
+    pub fn fakesign_parallel(
+        &mut self,
+        number_of_threads: NonZeroUsize,
+    ) -> Result<(), PreSwitchTicketError> {
+        self.signed_blob_header.signature.clear();
+
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+
+        self.padding = signed_blob_header::trucha_brute_force(
+            &signed_data,
+            Self::FAKESIGN_FIELD_OFFSET,
+            number_of_threads,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sign the ticket with the given private key, the issuer is the path of the certificate of the
+    /// key (like `Root-CA00000001-XS00000003`).
+    pub fn sign_with(
+        &mut self,
+        private_key: &PrivateKey,
+        issuer: &str,
+    ) -> Result<(), PreSwitchTicketError> {
+        self.signed_blob_header = SignedBlobHeader::new_unsigned(private_key, issuer)?;
+
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+        self.signed_blob_header.sign(&signed_data, private_key)?;
+
+        Ok(())
+    }
+
+    /// Verify the signature of the ticket using the key of its issuer stored on the given
+    /// certificate chain.
+    pub fn verify_signature(
+        &self,
+        certificate_chain: &CertificateChain,
+    ) -> Result<SignedBlobHeaderVerification, PreSwitchTicketError> {
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+
+        Ok(self
+            .signed_blob_header
+            .verify(&signed_data, certificate_chain)?)
+    }
+
     /// Either if this ticket was generated to be used only in a specific console (the associated
     /// title was purchased) or not.
     pub fn is_device_unique(&self) -> bool {
         self.device_id.is_some()
     }
 
-    /// Decrypt the title key.
+    /// Either if the ticket was issued for development consoles, the development common keys
+    /// will be used to decrypt the title key.
+    pub fn is_dev(&self) -> bool {
+        self.signed_blob_header.is_dev()
+    }
+
+    fn common_key(
+        &self,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<[u8; 16], PreSwitchTicketError> {
+        let is_dev = self.is_dev();
+
+        let key = |kind| {
+            key_provider
+                .key(kind)
+                .ok_or(PreSwitchTicketError::KeyNotFound(kind))
+        };
+
+        Ok(match cryptographic_method {
+            CryptographicMethod::Wii => key(KeyKind::WiiCommon {
+                kind: WiiCommonKeyKind::new(self.common_key_kind_index)?,
+                is_dev,
+            })?,
+
+            CryptographicMethod::Dsi => key(KeyKind::DsiCommon { is_dev })?,
+            CryptographicMethod::WiiU => key(KeyKind::WiiUCommon { is_dev })?,
+
+            CryptographicMethod::The3ds => wii_common_key::key_scrambler_3ds(
+                key(KeyKind::The3dsCommonKeyX { is_dev })?,
+                key(KeyKind::The3dsCommonKeyY {
+                    index: self.common_key_kind_index,
+                    is_dev,
+                })?,
+            ),
+        })
+    }
+
+    // The IV of the title key is the ticket ID on device unique Wii tickets and the title ID
+    // otherwise, adding 8 trailing zeroed bytes
+    fn title_key_iv(&self, cryptographic_method: CryptographicMethod) -> [u8; 16] {
+        let id = match cryptographic_method {
+            CryptographicMethod::Wii if self.is_device_unique() => self.ticket_id,
+
+            CryptographicMethod::Wii
+            | CryptographicMethod::Dsi
+            | CryptographicMethod::WiiU
+            | CryptographicMethod::The3ds => self.title_id.inner(),
+        };
+
+        #[allow(clippy::expect_used)]
+        [id.to_be_bytes(), [0; 8]]
+            .concat()
+            .try_into()
+            .expect("Will never fail, the `id` slice has always a size of 8")
+    }
+
+    /// Decrypt the title key with the common key given by the key provider, the development
+    /// common keys will be requested if the ticket was issued for development consoles (see
+    /// [Self::is_dev]).
     pub fn decrypt_title_key(
         &self,
         cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
     ) -> Result<[u8; 16], PreSwitchTicketError> {
-        match cryptographic_method {
-            CryptographicMethod::Wii => {
-                let id = if self.is_device_unique() {
-                    self.ticket_id
-                } else {
-                    self.title_id.inner()
-                };
+        let common_key = self.common_key(cryptographic_method, key_provider)?;
+        let cipher = Aes128CbcDec::new(
+            (&common_key).into(),
+            &self.title_key_iv(cryptographic_method).into(),
+        );
+
+        let mut title_key = self.encrypted_title_key;
 
-                #[allow(clippy::expect_used)]
-                let iv: [u8; 16] = [id.to_be_bytes(), [0; 8]]
-                    .concat()
-                    .try_into()
-                    .expect("Will never fail, the `id` slice has always a size of 8");
+        cipher.decrypt_padded_mut::<NoPadding>(&mut title_key)?;
 
-                let common_key_kind = WiiCommonKeyKind::new(self.common_key_kind_index)?;
-                let cipher = Aes128CbcDec::new((&common_key_kind.bytes()).into(), &iv.into());
+        Ok(title_key)
+    }
 
-                let mut title_key = self.encrypted_title_key;
+    /// Encrypt a new title key with the common key given by the key provider (selected by
+    /// [Self::common_key_kind_index]) and store it on the ticket.
+    ///
+    /// The title key must be set again after changing the title ID (or the ticket ID on device
+    /// unique Nintendo Wii tickets), as they are used to encrypt it.
+    pub fn set_title_key(
+        &mut self,
+        title_key: [u8; 16],
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<(), PreSwitchTicketError> {
+        let common_key = self.common_key(cryptographic_method, key_provider)?;
+        let cipher = Aes128CbcEnc::new(
+            (&common_key).into(),
+            &self.title_key_iv(cryptographic_method).into(),
+        );
 
-                cipher.decrypt_padded_mut::<NoPadding>(&mut title_key)?;
+        let mut encrypted_title_key = title_key;
 
-                Ok(title_key)
-            }
+        cipher
+            .encrypt_padded_mut::<NoPadding>(&mut encrypted_title_key, title_key.len())
+            .map_err(|_| PreSwitchTicketError::CryptographicPadError())?;
+
+        self.encrypted_title_key = encrypted_title_key;
+
+        Ok(())
+    }
+
+    /// Change the Nintendo Wii common key used to encrypt the title key, the title key will be
+    /// encrypted again. Useful to convert titles made for Korean or Wii U (vWii) consoles into
+    /// normal ones and vice versa.
+    pub fn change_common_key(
+        &mut self,
+        kind: WiiCommonKeyKind,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<(), PreSwitchTicketError> {
+        let title_key = self.decrypt_title_key(CryptographicMethod::Wii, key_provider)?;
+
+        self.common_key_kind_index = kind.identifier();
+        self.set_title_key(title_key, CryptographicMethod::Wii, key_provider)
+    }
+
+    /// Convert a device unique ticket (see [Self::is_device_unique]) into one valid for all
+    /// consoles: the device ID, the ticket ID and the ECC public key are cleared and the title key
+    /// is encrypted again (on the Nintendo Wii using the title ID as its IV instead of the ticket
+    /// ID). Does nothing on tickets that are not device unique.
+    ///
+    /// The ticket must be fakesigned (see [Self::fakesign]) or signed again afterwards.
+    pub fn depersonalize(
+        &mut self,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<(), PreSwitchTicketError> {
+        if !self.is_device_unique() {
+            return Ok(());
         }
+
+        let title_key = self.decrypt_title_key(cryptographic_method, key_provider)?;
+
+        self.device_id = None;
+        self.ticket_id = 0;
+        self.ecc_public_key = [0; 60];
+
+        self.set_title_key(title_key, cryptographic_method, key_provider)
+    }
+
+    // The hashed flag is shared with the disc one of the Nintendo 3DS, only the Nintendo Wii U
+    // has hashed contents
+    fn is_hashed(
+        title_metadata: &TitleMetadata,
+        content_selector: &ContentSelector,
+        cryptographic_method: CryptographicMethod,
+    ) -> Result<bool, PreSwitchTicketError> {
+        Ok(matches!(cryptographic_method, CryptographicMethod::WiiU)
+            && content_selector
+                .content_entry(title_metadata)?
+                .kind
+                .is_hashed())
     }
 
     /// Get a decryptor of a content, where the `stream` is the content bytes.
+    ///
+    /// Hashed contents (only present on the Nintendo Wii U) are not supported, use
+    /// [Self::hashed_cryptographic_stream] instead. On the Nintendo 3DS the same flag means that
+    /// the content is stored on a disc, those contents are not hashed.
     pub fn cryptographic_stream<T: Seek>(
         &self,
         stream: T,
         title_metadata: &TitleMetadata,
         content_selector: ContentSelector,
         cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
     ) -> Result<AesCbcStream<T>, PreSwitchTicketError> {
-        match cryptographic_method {
-            CryptographicMethod::Wii => {
-                let title_key = self.decrypt_title_key(cryptographic_method)?;
+        if Self::is_hashed(title_metadata, &content_selector, cryptographic_method)? {
+            return Err(PreSwitchTicketError::HashedContent());
+        }
 
-                // Add 14 trailing zeroed bytes to the IV
-                let mut iv = Vec::from(content_selector.index(title_metadata)?.to_be_bytes());
-                iv.append(&mut Vec::from([0; 14]));
+        let title_key = self.decrypt_title_key(cryptographic_method, key_provider)?;
 
-                #[allow(clippy::expect_used)]
-                let iv: [u8; 16] = iv
-                    .try_into()
-                    .expect("Will never fail, the `content_index` is always 16 bits");
+        // All the platforms use the content index as the IV, adding 14 trailing zeroed bytes
+        let mut iv = Vec::from(content_selector.index(title_metadata)?.to_be_bytes());
+        iv.append(&mut Vec::from([0; 14]));
 
-                Ok(AesCbcStream::new(stream, title_key, iv)?)
-            }
+        #[allow(clippy::expect_used)]
+        let iv: [u8; 16] = iv
+            .try_into()
+            .expect("Will never fail, the `content_index` is always 16 bits");
+
+        Ok(AesCbcStream::new(stream, title_key, iv)?)
+    }
+
+    /// Get a decryptor of a hashed content (only present on the Nintendo Wii U), where the
+    /// `stream` is the content bytes. The data of the content will be returned without its hashes.
+    ///
+    /// Contents that are not hashed (including all the ones of other platforms) are not supported,
+    /// use [Self::cryptographic_stream] instead.
+    pub fn hashed_cryptographic_stream<T: Read + Seek>(
+        &self,
+        stream: T,
+        title_metadata: &TitleMetadata,
+        content_selector: ContentSelector,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<HashedContentStream<T>, PreSwitchTicketError> {
+        if !Self::is_hashed(title_metadata, &content_selector, cryptographic_method)? {
+            return Err(PreSwitchTicketError::NotHashedContent());
         }
+
+        let title_key = self.decrypt_title_key(cryptographic_method, key_provider)?;
+        let encrypted_size = content_selector.content_entry(title_metadata)?.size;
+
+        Ok(HashedContentStream::new(stream, title_key, encrypted_size)?)
     }
 }
 
@@ -350,11 +603,23 @@ pub enum PreSwitchTicketError {
     #[error("Unable to do cryptographic operation over the data, padding error: {0}")]
     CryptographicUnpadError(#[from] block_padding::UnpadError),
 
+    #[error("Unable to do cryptographic operation over the data, padding error")]
+    CryptographicPadError(),
+
     #[error("Ticket V1 error: {0}")]
     TicketV1Error(#[from] v1::PreSwitchTicketV1Error),
 
     #[error("Title metadata error: {0}")]
     TitleMetadataError(#[from] TitleMetadataError),
+
+    #[error("The key is not available on the key provider: {0:?}")]
+    KeyNotFound(KeyKind),
+
+    #[error("Hashed contents need to be decrypted with a hashed cryptographic stream")]
+    HashedContent(),
+
+    #[error("Only hashed contents can be decrypted with a hashed cryptographic stream")]
+    NotHashedContent(),
 }
 
 bitflags! {
@@ -481,7 +746,8 @@ impl PreSwitchTicketLimitEntry {
                 minutes: associated_value,
             },
 
-            2 => Self::LaunchLimit {
+            // The launch limit entries are identified by 4, the same value used when dumping
+            4 => Self::LaunchLimit {
                 number_of_launches: associated_value,
             },
 
@@ -508,3 +774,447 @@ impl PreSwitchTicketLimitEntry {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_provider::{EmbeddedKeyProvider, MemoryKeyProvider};
+    use crate::test_fixtures;
+    use sha1::{Digest, Sha1};
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    /// A key provider that records the kinds of the keys requested to it.
+    #[derive(Default)]
+    struct RecordingKeyProvider(RefCell<Vec<KeyKind>>);
+
+    impl KeyProvider for RecordingKeyProvider {
+        fn key(&self, kind: KeyKind) -> Option<[u8; 16]> {
+            self.0.borrow_mut().push(kind);
+
+            Some([0; 16])
+        }
+    }
+
+    fn requested_keys(
+        ticket: &PreSwitchTicket,
+        cryptographic_method: CryptographicMethod,
+    ) -> Vec<KeyKind> {
+        let key_provider = RecordingKeyProvider::default();
+        ticket
+            .decrypt_title_key(cryptographic_method, &key_provider)
+            .unwrap();
+
+        key_provider.0.into_inner()
+    }
+
+    fn ticket_with_issuer(issuer: &str) -> PreSwitchTicket {
+        let mut ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        ticket.signed_blob_header.issuer = issuer.to_string();
+
+        ticket
+    }
+
+    fn dump(ticket: &PreSwitchTicket) -> Vec<u8> {
+        let mut stream = Cursor::new(Vec::new());
+        ticket.dump(&mut stream).unwrap();
+
+        stream.into_inner()
+    }
+
+    #[test]
+    fn fakesign() {
+        let mut ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        ticket.fakesign().unwrap();
+
+        let fakesigned = dump(&ticket);
+        let signed_data_offset = ticket.signed_blob_header.signed_data_offset();
+
+        // RSA-2048 signature, after its kind
+        assert!(fakesigned[4..4 + 256].iter().all(|byte| *byte == 0));
+        assert_eq!(Sha1::digest(&fakesigned[signed_data_offset..])[0], 0);
+
+        ticket.fakesign().unwrap();
+        assert_eq!(dump(&ticket), fakesigned);
+
+        ticket
+            .fakesign_parallel(NonZeroUsize::new(4).unwrap())
+            .unwrap();
+        assert_eq!(dump(&ticket), fakesigned);
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let mut ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        let certificate_chain = test_fixtures::signing_certificate_chain();
+
+        ticket
+            .sign_with(&test_fixtures::private_key(), "Root-CA00000001-XS00000003")
+            .unwrap();
+
+        assert_eq!(
+            ticket.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Authentic
+        );
+
+        // Flip a bit of the title version
+        let mut tampered = dump(&ticket);
+        tampered[0x1E7] ^= 1;
+        let tampered = PreSwitchTicket::new(Cursor::new(tampered)).unwrap();
+
+        assert_eq!(
+            tampered.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Invalid
+        );
+    }
+
+    #[test]
+    fn title_key_iv_depends_on_method() {
+        let mut key_provider = MemoryKeyProvider::new();
+        key_provider
+            .insert(
+                KeyKind::WiiCommon {
+                    kind: WiiCommonKeyKind::Normal,
+                    is_dev: false,
+                },
+                [0x01; 16],
+            )
+            .insert(KeyKind::WiiUCommon { is_dev: false }, [0x02; 16])
+            .insert(KeyKind::The3dsCommonKeyX { is_dev: false }, [0x03; 16])
+            .insert(
+                KeyKind::The3dsCommonKeyY {
+                    index: 0,
+                    is_dev: false,
+                },
+                [0x04; 16],
+            );
+
+        let title_key = [0xAB; 16];
+
+        for (cryptographic_method, is_ticket_id_used) in [
+            (CryptographicMethod::Wii, true),
+            (CryptographicMethod::WiiU, false),
+            (CryptographicMethod::The3ds, false),
+        ] {
+            let mut ticket =
+                PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+            ticket
+                .set_title_key(title_key, cryptographic_method, &key_provider)
+                .unwrap();
+            let encrypted_title_key = ticket.encrypted_title_key;
+
+            ticket.device_id = Some(0x0403_0201);
+            ticket
+                .set_title_key(title_key, cryptographic_method, &key_provider)
+                .unwrap();
+
+            assert_eq!(
+                ticket.encrypted_title_key != encrypted_title_key,
+                is_ticket_id_used
+            );
+            assert_eq!(
+                ticket
+                    .decrypt_title_key(cryptographic_method, &key_provider)
+                    .unwrap(),
+                title_key
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_data_round_trip() {
+        let mut bytes = test_fixtures::ticket_bytes();
+
+        // Reserved byte after the title key and unknown bytes after the common key index
+        bytes[0x1CF] = 0xA5;
+        for (i, byte) in bytes[0x1F2..0x221].iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+
+        // A launch limit entry of 10 launches, the first of the limit entries at the end
+        let limit_entries_offset = bytes.len() - 64;
+        bytes[limit_entries_offset..limit_entries_offset + 8]
+            .copy_from_slice(&[0, 0, 0, 4, 0, 0, 0, 10]);
+
+        let ticket = PreSwitchTicket::new(Cursor::new(&bytes)).unwrap();
+
+        assert!(matches!(
+            ticket.limit_entries[0],
+            PreSwitchTicketLimitEntry::LaunchLimit {
+                number_of_launches: 10
+            }
+        ));
+        assert_eq!(dump(&ticket), bytes);
+    }
+
+    #[test]
+    fn common_key_selection() {
+        for (issuer, is_dev) in [
+            ("Root-CA00000001-XS00000003", false),
+            ("Root-CA00000002-XS00000006", true),
+        ] {
+            let ticket = ticket_with_issuer(issuer);
+            assert_eq!(ticket.is_dev(), is_dev);
+
+            assert_eq!(
+                requested_keys(&ticket, CryptographicMethod::Wii),
+                [KeyKind::WiiCommon {
+                    kind: WiiCommonKeyKind::Normal,
+                    is_dev
+                }]
+            );
+            assert_eq!(
+                requested_keys(&ticket, CryptographicMethod::WiiU),
+                [KeyKind::WiiUCommon { is_dev }]
+            );
+            assert_eq!(
+                requested_keys(&ticket, CryptographicMethod::The3ds),
+                [
+                    KeyKind::The3dsCommonKeyX { is_dev },
+                    KeyKind::The3dsCommonKeyY { index: 0, is_dev }
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn dev_title_key_round_trip() {
+        let mut ticket = ticket_with_issuer("Root-CA00000002-XS00000006");
+        let title_key = [0xAB; 16];
+
+        ticket
+            .set_title_key(title_key, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+        let dev_encrypted_title_key = ticket.encrypted_title_key;
+
+        assert_eq!(
+            ticket
+                .decrypt_title_key(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+                .unwrap(),
+            title_key
+        );
+
+        // The same title key encrypted with the retail common key
+        ticket.signed_blob_header.issuer = "Root-CA00000001-XS00000003".to_string();
+        ticket
+            .set_title_key(title_key, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert_ne!(ticket.encrypted_title_key, dev_encrypted_title_key);
+    }
+
+    #[test]
+    fn dsi_common_key_selection() {
+        for (issuer, is_dev) in [
+            ("Root-CA00000001-XS00000003", false),
+            ("Root-CA00000002-XS00000006", true),
+        ] {
+            let ticket = ticket_with_issuer(issuer);
+
+            assert_eq!(
+                requested_keys(&ticket, CryptographicMethod::Dsi),
+                [KeyKind::DsiCommon { is_dev }]
+            );
+        }
+
+        // The common key of development DSi consoles is not embedded
+        let ticket = ticket_with_issuer("Root-CA00000002-XS00000006");
+
+        assert!(matches!(
+            ticket.decrypt_title_key(CryptographicMethod::Dsi, &EmbeddedKeyProvider),
+            Err(PreSwitchTicketError::KeyNotFound(KeyKind::DsiCommon {
+                is_dev: true
+            }))
+        ));
+    }
+
+    #[test]
+    fn dsi_title_key_iv_is_title_id() {
+        let mut ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        let title_key = [0xAB; 16];
+
+        ticket
+            .set_title_key(title_key, CryptographicMethod::Dsi, &EmbeddedKeyProvider)
+            .unwrap();
+        let encrypted_title_key = ticket.encrypted_title_key;
+
+        ticket.device_id = Some(0x0403_0201);
+        ticket
+            .set_title_key(title_key, CryptographicMethod::Dsi, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert_eq!(ticket.encrypted_title_key, encrypted_title_key);
+        assert_eq!(
+            ticket
+                .decrypt_title_key(CryptographicMethod::Dsi, &EmbeddedKeyProvider)
+                .unwrap(),
+            title_key
+        );
+    }
+
+    #[test]
+    fn hashed_stream_rejects_not_hashed_content() {
+        let ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        let title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[0x10000])))
+                .unwrap();
+
+        assert!(matches!(
+            ticket.hashed_cryptographic_stream(
+                Cursor::new(vec![0; 0x10000]),
+                &title_metadata,
+                title_metadata.select_with_index(0),
+                CryptographicMethod::WiiU,
+                &EmbeddedKeyProvider,
+            ),
+            Err(PreSwitchTicketError::NotHashedContent())
+        ));
+    }
+
+    #[test]
+    fn change_common_key() {
+        let mut ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        let title_key = ticket
+            .decrypt_title_key(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+        let encrypted_title_key = ticket.encrypted_title_key;
+
+        ticket
+            .change_common_key(WiiCommonKeyKind::Korean, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert_eq!(ticket.common_key_kind_index, 1);
+        assert_ne!(ticket.encrypted_title_key, encrypted_title_key);
+        assert_eq!(
+            ticket
+                .decrypt_title_key(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+                .unwrap(),
+            title_key
+        );
+
+        ticket
+            .change_common_key(WiiCommonKeyKind::Normal, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert_eq!(ticket.common_key_kind_index, 0);
+        assert_eq!(ticket.encrypted_title_key, encrypted_title_key);
+    }
+
+    #[test]
+    fn depersonalize() {
+        let mut ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+        let title_key = [0xAB; 16];
+
+        // Untouched if not device unique
+        ticket
+            .depersonalize(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+        assert_eq!(dump(&ticket), test_fixtures::ticket_bytes());
+
+        ticket.device_id = Some(0x0403_0201);
+        ticket.ecc_public_key = [0x77; 60];
+        ticket
+            .set_title_key(title_key, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+
+        ticket
+            .depersonalize(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert!(!ticket.is_device_unique());
+        assert_eq!(ticket.ticket_id, 0);
+        assert_eq!(ticket.ecc_public_key, [0; 60]);
+        assert_eq!(
+            ticket
+                .decrypt_title_key(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+                .unwrap(),
+            title_key
+        );
+
+        let ticket = PreSwitchTicket::new(Cursor::new(dump(&ticket))).unwrap();
+
+        assert_eq!(ticket.device_id, None);
+        assert_eq!(
+            ticket
+                .decrypt_title_key(CryptographicMethod::Wii, &EmbeddedKeyProvider)
+                .unwrap(),
+            title_key
+        );
+    }
+
+    #[test]
+    fn disc_flag_is_only_hashed_on_wii_u() {
+        let mut key_provider = MemoryKeyProvider::new();
+        key_provider
+            .insert(KeyKind::WiiUCommon { is_dev: false }, [0x02; 16])
+            .insert(KeyKind::The3dsCommonKeyX { is_dev: false }, [0x03; 16])
+            .insert(
+                KeyKind::The3dsCommonKeyY {
+                    index: 0,
+                    is_dev: false,
+                },
+                [0x04; 16],
+            );
+
+        let ticket = PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap();
+
+        // Kind of the first content entry: normal (encrypted on the Nintendo 3DS) and hashed (or
+        // stored on a disc on the Nintendo 3DS)
+        let mut bytes = test_fixtures::title_metadata_bytes(&[0x10000]);
+        bytes[0x1EA..0x1EC].copy_from_slice(&0x0003_u16.to_be_bytes());
+        let title_metadata = TitleMetadata::new(Cursor::new(bytes)).unwrap();
+        let content_selector = title_metadata.select_with_index(0);
+
+        let kind = content_selector
+            .content_entry(&title_metadata)
+            .unwrap()
+            .kind;
+        assert!(kind.is_encrypted_3ds() && kind.is_disc_3ds());
+
+        let content = || Cursor::new(vec![0; 0x10000]);
+
+        assert!(
+            ticket
+                .cryptographic_stream(
+                    content(),
+                    &title_metadata,
+                    content_selector,
+                    CryptographicMethod::The3ds,
+                    &key_provider,
+                )
+                .is_ok()
+        );
+        assert!(matches!(
+            ticket.hashed_cryptographic_stream(
+                content(),
+                &title_metadata,
+                content_selector,
+                CryptographicMethod::The3ds,
+                &key_provider,
+            ),
+            Err(PreSwitchTicketError::NotHashedContent())
+        ));
+
+        assert!(matches!(
+            ticket.cryptographic_stream(
+                content(),
+                &title_metadata,
+                content_selector,
+                CryptographicMethod::WiiU,
+                &key_provider,
+            ),
+            Err(PreSwitchTicketError::HashedContent())
+        ));
+        assert!(
+            ticket
+                .hashed_cryptographic_stream(
+                    content(),
+                    &title_metadata,
+                    content_selector,
+                    CryptographicMethod::WiiU,
+                    &key_provider,
+                )
+                .is_ok()
+        );
+    }
+}
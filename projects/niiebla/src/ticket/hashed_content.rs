@@ -0,0 +1,227 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Implementation of the decryption of hashed contents, present on the Nintendo Wii U.
+
+use aes::cipher::{BlockDecryptMut, KeyIvInit, block_padding::NoPadding};
+use std::io::{self, Read, Seek, SeekFrom};
+use util::Aes128CbcDec;
+use util::StreamPin;
+
+const BLOCK_SIZE: u64 = 0x10000;
+const HASHES_SIZE: usize = 0x400;
+const DATA_SIZE: usize = BLOCK_SIZE as usize - HASHES_SIZE;
+const H0_HASH_SIZE: usize = 20;
+const NUMBER_OF_H0_HASHES: u64 = 16;
+
+/// Stream of the decrypted data of a hashed content.
+///
+/// Hashed contents are stored in blocks of `0x10000` bytes, each one made of a header of `0x400`
+/// bytes with a tree of hashes (H0 to H3) followed by `0xFC00` bytes of data. The header is
+/// encrypted with a zeroed IV and the data uses as IV the H0 hash of the block, only the data is
+/// returned by this stream.
+pub struct HashedContentStream<T: Seek> {
+    stream: StreamPin<T>,
+    title_key: [u8; 16],
+    position: u64,
+    len: u64,
+    cached_block: Option<(u64, Box<[u8; DATA_SIZE]>)>,
+}
+
+impl<T: Read + Seek> HashedContentStream<T> {
+    /// Create a new decryption stream, where `stream` is positioned at the start of the
+    /// encrypted content of the given size.
+    pub fn new(stream: T, title_key: [u8; 16], encrypted_size: u64) -> io::Result<Self> {
+        Ok(Self {
+            stream: StreamPin::new(stream)?,
+            title_key,
+            position: 0,
+            len: encrypted_size / BLOCK_SIZE * DATA_SIZE as u64,
+            cached_block: None,
+        })
+    }
+
+    /// Get the stored stream.
+    pub fn into_inner(self) -> T {
+        self.stream.into_inner()
+    }
+
+    fn decrypt(&self, buffer: &mut [u8], iv: [u8; 16]) -> io::Result<()> {
+        Aes128CbcDec::new(&self.title_key.into(), &iv.into())
+            .decrypt_padded_mut::<NoPadding>(buffer)
+            .map_err(|err| io::Error::other(format!("Unable to decrypt the buffer: {err}")))?;
+
+        Ok(())
+    }
+
+    fn decrypt_block(&mut self, block: u64) -> io::Result<Box<[u8; DATA_SIZE]>> {
+        self.stream.seek_from_pin((block * BLOCK_SIZE) as i64)?;
+
+        let mut hashes = [0; HASHES_SIZE];
+        self.stream.read_exact(&mut hashes)?;
+        self.decrypt(&mut hashes, [0; 16])?;
+
+        // The H0 hashes are stored at the start of the header, one per each block of a group of
+        // 16 blocks
+        let iv_offset = (block % NUMBER_OF_H0_HASHES) as usize * H0_HASH_SIZE;
+
+        #[allow(clippy::expect_used)]
+        let iv: [u8; 16] = hashes[iv_offset..iv_offset + 16]
+            .try_into()
+            .expect("Will never fail, the slice has always a size of 16");
+
+        let mut data = Box::new([0; DATA_SIZE]);
+        self.stream.read_exact(data.as_mut_slice())?;
+        self.decrypt(data.as_mut_slice(), iv)?;
+
+        Ok(data)
+    }
+}
+
+impl<T: Read + Seek> Read for HashedContentStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block = self.position / DATA_SIZE as u64;
+        let offset = (self.position % DATA_SIZE as u64) as usize;
+
+        let data = match self.cached_block.take() {
+            Some((cached_block, data)) if cached_block == block => data,
+            _ => self.decrypt_block(block)?,
+        };
+
+        let read_len = buf.len().min(data.len() - offset);
+        buf[..read_len].copy_from_slice(&data[offset..offset + read_len]);
+
+        self.cached_block = Some((block, data));
+        self.position += read_len as u64;
+
+        Ok(read_len)
+    }
+}
+
+impl<T: Read + Seek> Seek for HashedContentStream<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = new_position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+    use std::io::Cursor;
+    use util::Aes128CbcEnc;
+
+    const TITLE_KEY: [u8; 16] = [0x42; 16];
+
+    fn encrypt(buffer: &mut [u8], iv: [u8; 16]) {
+        Aes128CbcEnc::new(&TITLE_KEY.into(), &iv.into())
+            .encrypt_padded_mut::<NoPadding>(buffer, buffer.len())
+            .unwrap();
+    }
+
+    /// The decrypted data of the content, two blocks with different bytes on each one.
+    fn data() -> Vec<u8> {
+        (0..DATA_SIZE * 2).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A content of two blocks with dummy H0 hashes as the IV of its data.
+    fn encrypted_content() -> Vec<u8> {
+        let mut content = vec![];
+
+        for (block, data) in data().chunks(DATA_SIZE).enumerate() {
+            let mut hashes = [0; HASHES_SIZE];
+            for (i, hash) in hashes[..H0_HASH_SIZE * NUMBER_OF_H0_HASHES as usize]
+                .chunks_mut(H0_HASH_SIZE)
+                .enumerate()
+            {
+                hash.fill((i * 0x10 + 1) as u8);
+            }
+
+            let iv_offset = block * H0_HASH_SIZE;
+            let iv: [u8; 16] = hashes[iv_offset..iv_offset + 16].try_into().unwrap();
+
+            let mut data = data.to_vec();
+            encrypt(&mut hashes, [0; 16]);
+            encrypt(&mut data, iv);
+
+            content.extend_from_slice(&hashes);
+            content.extend_from_slice(&data);
+        }
+
+        content
+    }
+
+    fn stream() -> HashedContentStream<Cursor<Vec<u8>>> {
+        let content = encrypted_content();
+        let encrypted_size = content.len() as u64;
+
+        HashedContentStream::new(Cursor::new(content), TITLE_KEY, encrypted_size).unwrap()
+    }
+
+    #[test]
+    fn decrypt() {
+        let mut decrypted = vec![];
+        stream().read_to_end(&mut decrypted).unwrap();
+
+        assert_eq!(decrypted, data());
+    }
+
+    #[test]
+    fn read_across_blocks() {
+        let mut stream = stream();
+        stream
+            .seek(SeekFrom::Start(DATA_SIZE as u64 - 0x10))
+            .unwrap();
+
+        let mut buffer = [0; 0x20];
+        stream.read_exact(&mut buffer).unwrap();
+
+        assert_eq!(buffer[..], data()[DATA_SIZE - 0x10..DATA_SIZE + 0x10]);
+    }
+
+    #[test]
+    fn seek() {
+        let data = data();
+        let mut stream = stream();
+        let mut buffer = [0; 8];
+
+        assert_eq!(
+            stream.seek(SeekFrom::End(-8)).unwrap(),
+            data.len() as u64 - 8
+        );
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[..], data[data.len() - 8..]);
+        assert_eq!(stream.read(&mut buffer).unwrap(), 0);
+
+        // Back to the first block
+        assert_eq!(stream.seek(SeekFrom::Current(-0xFC08)).unwrap(), 0xFBF8);
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[..], data[0xFBF8..0xFC00]);
+
+        assert_eq!(stream.seek(SeekFrom::Start(3)).unwrap(), 3);
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[..], data[3..11]);
+
+        assert!(stream.seek(SeekFrom::Current(-12)).is_err());
+    }
+}
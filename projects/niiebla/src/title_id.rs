@@ -61,6 +61,12 @@ impl TitleId {
         *self = Self::new_with_halfs(higher_half, self.lower_half());
     }
 
+    /// If the title ID is the one of an IOS of the Nintendo Wii (`00000001-00000003` to
+    /// `00000001-000000FF`).
+    pub fn is_ios_wii(&self) -> bool {
+        self.higher_half() == 0x00000001 && (0x00000003..=0x000000FF).contains(&self.lower_half())
+    }
+
     /// Get a wrapper that can display the title ID with ASCII characters in its lower half, if the
     /// character is not visible a fallback to the normal display will be made.
     pub fn display_ascii(&self) -> TitleIdAsciiDisplay<'_> {
@@ -149,6 +155,19 @@ impl Display for TitleIdWiiPlatformDisplay<'_> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn ios_wii() {
+        assert!(TitleId::new_with_halfs(0x00000001, 58).is_ios_wii());
+        assert!(TitleId::new_with_halfs(0x00000001, 254).is_ios_wii());
+
+        // boot2, System Menu and BC
+        assert!(!TitleId::new_with_halfs(0x00000001, 0x00000001).is_ios_wii());
+        assert!(!TitleId::new_with_halfs(0x00000001, 0x00000002).is_ios_wii());
+        assert!(!TitleId::new_with_halfs(0x00000001, 0x00000100).is_ios_wii());
+
+        assert!(!TitleId::new(TEST_ID_NUMBER).is_ios_wii());
+    }
+
     // Fake ID "4A4132BC-HAGA"
     const TEST_ID_NUMBER: u64 = 5350613616540337985;
 
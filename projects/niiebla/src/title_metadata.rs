@@ -6,20 +6,29 @@
 
 //! Implementation of the binary file format used by Nintendo to store title metadata.
 
-use crate::signed_blob_header::{SignedBlobHeader, SignedBlobHeaderError};
+use crate::CertificateChain;
+use crate::signed_blob_header::{
+    self, PrivateKey, SignedBlobHeader, SignedBlobHeaderError, SignedBlobHeaderVerification,
+};
 use crate::title_id::TitleId;
+use bitflags::bitflags;
 use byteorder::{BE, LE, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
 use std::io;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
+use std::num::NonZeroUsize;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 use util::{ReadEx, WriteEx};
 
 pub mod content_selector;
+pub mod wii_ratings;
 
 use content_selector::{ContentSelector, ContentSelectorMethod};
+use wii_ratings::TitleMetadataWiiRatings;
 
 /// Manifest data regard the title itself, its structure and allowed system access (Also known as
 /// `TMD` data).
@@ -76,7 +85,8 @@ pub struct TitleMetadata {
     pub group_id: u16,
 
     /// Bitflags of access right to the hardware, its meaning depends on the platform, the access
-    /// to this entry is recommended to use platform aware methods like [Self::has_ppc_access_wii] or [Self::has_dvd_access_wii].
+    /// to this entry is recommended to use platform aware methods like [Self::typed_access_rights],
+    /// [Self::has_ppc_access_wii] or [Self::has_dvd_access_wii].
     pub access_rights: u32,
 
     /// The version of the title.
@@ -85,9 +95,18 @@ pub struct TitleMetadata {
     /// The index value of the content entry where the boot data is located.
     pub boot_content_index: u16,
 
+    /// The minor version of the title, never used by the system so it's usually the value
+    /// changed while fakesigning (see [Self::fakesign]).
+    pub title_minor_version: u16,
+
     /// Platform dependant data.
     pub platform_data: TitleMetadataPlatformData,
 
+    /// The raw bytes of the block of platform dependant data, the reserved and unknown bytes
+    /// are kept as is to dump the title metadata without changes. The values of
+    /// [Self::platform_data] take precedence over the ones stored here.
+    pub platform_data_raw: [u8; 62],
+
     /// Extra data only present on the v1 version of a title metadata.
     pub version_1_extension: Option<TitleMetadataV1>,
 
@@ -96,6 +115,11 @@ pub struct TitleMetadata {
 }
 
 impl TitleMetadata {
+    const BOOT2_TITLE_ID: u64 = 0x00000001_00000001;
+
+    /// Offset of [Self::title_minor_version] inside the signed data.
+    const FAKESIGN_FIELD_OFFSET: usize = 162;
+
     /// Create a new installable Wad representation.
     pub fn new<T: Read + Seek>(mut stream: T) -> Result<Self, TitleMetadataError> {
         let signed_blob_header = SignedBlobHeader::new(&mut stream)?;
@@ -120,26 +144,27 @@ impl TitleMetadata {
 
         let group_id = stream.read_u16::<BE>()?;
 
+        let platform_data_raw = util::read_exact!(stream, 62)?;
+        let mut platform_data_stream = Cursor::new(platform_data_raw);
+
         match platform_data {
-            TitleMetadataPlatformData::DSi | TitleMetadataPlatformData::WiiU => {
-                stream.seek_relative(62)?;
-            }
+            TitleMetadataPlatformData::DSi | TitleMetadataPlatformData::WiiU => (),
 
             TitleMetadataPlatformData::Console3ds {
                 ref mut public_save_data_size,
                 ref mut private_save_data_size,
                 ref mut srl_flag,
             } => {
-                *public_save_data_size = stream.read_u32::<LE>()?;
-                *private_save_data_size = stream.read_u32::<LE>()?;
+                *public_save_data_size = platform_data_stream.read_u32::<LE>()?;
+                *private_save_data_size = platform_data_stream.read_u32::<LE>()?;
 
                 // Skip four unknown bytes
-                stream.seek_relative(4)?;
+                platform_data_stream.seek_relative(4)?;
 
-                *srl_flag = stream.read_u8()?;
+                *srl_flag = platform_data_stream.read_u8()?;
 
                 // Skip 49 unknown bytes
-                stream.seek_relative(49)?;
+                platform_data_stream.seek_relative(49)?;
             }
 
             TitleMetadataPlatformData::Wii {
@@ -151,20 +176,24 @@ impl TitleMetadata {
                 *is_wii_u_vwii_only_title = first_reserved_byte;
 
                 // Skip 2 zeroed bytes
-                stream.seek_relative(2)?;
+                platform_data_stream.seek_relative(2)?;
 
-                *region =
-                    TitleMetadataPlatformDataWiiRegion::from_identifier(stream.read_u16::<BE>()?)?;
+                *region = TitleMetadataPlatformDataWiiRegion::from_identifier(
+                    platform_data_stream.read_u16::<BE>()?,
+                )?;
 
-                *ratings = util::read_exact!(stream, 16)?;
+                *ratings = TitleMetadataWiiRatings::from_bytes(util::read_exact!(
+                    platform_data_stream,
+                    16
+                )?);
 
                 // Skip 12 reserved bytes
-                stream.seek_relative(12)?;
+                platform_data_stream.seek_relative(12)?;
 
-                *ipc_mask = util::read_exact!(stream, 12)?;
+                *ipc_mask = util::read_exact!(platform_data_stream, 12)?;
 
                 // Skip 18 reserved bytes
-                stream.seek_relative(18)?;
+                platform_data_stream.seek_relative(18)?;
             }
         }
 
@@ -173,8 +202,7 @@ impl TitleMetadata {
         let number_of_content_entries = stream.read_u16::<BE>()?;
         let boot_content_index = stream.read_u16::<BE>()?;
 
-        // Skip the title minor version as it was never used
-        stream.seek_relative(2)?;
+        let title_minor_version = stream.read_u16::<BE>()?;
 
         let version_1_extension = match format_version {
             0 => None,
@@ -198,9 +226,11 @@ impl TitleMetadata {
             system_runtime_title_id,
             title_id,
             platform_data,
+            platform_data_raw,
             group_id,
             title_version,
             boot_content_index,
+            title_minor_version,
             access_rights,
             version_1_extension,
             content_chunk_entries,
@@ -246,26 +276,26 @@ impl TitleMetadata {
         self.platform_data.dump_identifier(&mut stream)?;
         stream.write_u16::<BE>(self.group_id)?;
 
+        let mut platform_data_stream = Cursor::new(self.platform_data_raw);
+
         match &self.platform_data {
-            TitleMetadataPlatformData::DSi | TitleMetadataPlatformData::WiiU => {
-                stream.write_zeroed(62)?;
-            }
+            TitleMetadataPlatformData::DSi | TitleMetadataPlatformData::WiiU => (),
 
             TitleMetadataPlatformData::Console3ds {
                 public_save_data_size,
                 private_save_data_size,
                 srl_flag,
             } => {
-                stream.write_u32::<LE>(*public_save_data_size)?;
-                stream.write_u32::<LE>(*private_save_data_size)?;
+                platform_data_stream.write_u32::<LE>(*public_save_data_size)?;
+                platform_data_stream.write_u32::<LE>(*private_save_data_size)?;
 
                 // Skip four unknown bytes
-                stream.write_zeroed(4)?;
+                platform_data_stream.seek_relative(4)?;
 
-                stream.write_u8(*srl_flag)?;
+                platform_data_stream.write_u8(*srl_flag)?;
 
                 // Skip 49 unknown bytes
-                stream.write_zeroed(49)?;
+                platform_data_stream.seek_relative(49)?;
             }
 
             TitleMetadataPlatformData::Wii {
@@ -274,24 +304,25 @@ impl TitleMetadata {
                 ratings,
                 ipc_mask,
             } => {
-                stream.write_zeroed(2)?;
+                platform_data_stream.seek_relative(2)?;
 
-                region.dump_identifier(&mut stream)?;
+                region.dump_identifier(&mut platform_data_stream)?;
 
-                stream.write_all(ratings)?;
-                stream.write_zeroed(12)?;
-                stream.write_all(ipc_mask)?;
-                stream.write_zeroed(18)?;
+                platform_data_stream.write_all(&ratings.bytes())?;
+                platform_data_stream.seek_relative(12)?;
+                platform_data_stream.write_all(ipc_mask)?;
+                platform_data_stream.seek_relative(18)?;
             }
         }
 
+        stream.write_all(platform_data_stream.get_ref())?;
+
         stream.write_u32::<BE>(self.access_rights)?;
         stream.write_u16::<BE>(self.title_version)?;
         stream.write_u16::<BE>(self.content_chunk_entries.len() as u16)?;
         stream.write_u16::<BE>(self.boot_content_index)?;
 
-        // Skip the title minor version as it was never used
-        stream.seek_relative(2)?;
+        stream.write_u16::<BE>(self.title_minor_version)?;
 
         if let Some(version_1_extension) = &self.version_1_extension {
             version_1_extension.dump(&mut stream)?;
@@ -304,25 +335,95 @@ impl TitleMetadata {
         Ok(())
     }
 
+    /// Get a typed view of [Self::access_rights] given the platform of the title, only the
+    /// access rights of Nintendo Wii titles have named flags.
+    pub fn typed_access_rights(&self) -> TitleMetadataAccessRights {
+        match self.platform_data {
+            TitleMetadataPlatformData::DSi => TitleMetadataAccessRights::DSi(self.access_rights),
+
+            TitleMetadataPlatformData::Wii { .. } => TitleMetadataAccessRights::Wii(
+                TitleMetadataWiiAccessRights::from_bits_retain(self.access_rights),
+            ),
+
+            TitleMetadataPlatformData::Console3ds { .. } => {
+                TitleMetadataAccessRights::Console3ds(self.access_rights)
+            }
+
+            TitleMetadataPlatformData::WiiU => TitleMetadataAccessRights::WiiU(self.access_rights),
+        }
+    }
+
+    /// Replace [Self::access_rights] with a typed view, that must match the platform of the
+    /// title.
+    pub fn set_typed_access_rights(
+        &mut self,
+        access_rights: TitleMetadataAccessRights,
+    ) -> Result<(), TitleMetadataError> {
+        match (&self.platform_data, access_rights) {
+            (TitleMetadataPlatformData::DSi, TitleMetadataAccessRights::DSi(_))
+            | (TitleMetadataPlatformData::Wii { .. }, TitleMetadataAccessRights::Wii(_))
+            | (
+                TitleMetadataPlatformData::Console3ds { .. },
+                TitleMetadataAccessRights::Console3ds(_),
+            )
+            | (TitleMetadataPlatformData::WiiU, TitleMetadataAccessRights::WiiU(_)) => {
+                self.access_rights = access_rights.bits();
+
+                Ok(())
+            }
+
+            _ => Err(TitleMetadataError::ActionInvalid()),
+        }
+    }
+
+    fn wii_access_rights(&self) -> Result<TitleMetadataWiiAccessRights, TitleMetadataError> {
+        match self.typed_access_rights() {
+            TitleMetadataAccessRights::Wii(access_rights) => Ok(access_rights),
+            _ => Err(TitleMetadataError::ActionInvalid()),
+        }
+    }
+
+    fn set_wii_access_right(
+        &mut self,
+        access_right: TitleMetadataWiiAccessRights,
+        value: bool,
+    ) -> Result<(), TitleMetadataError> {
+        let mut access_rights = self.wii_access_rights()?;
+        access_rights.set(access_right, value);
+
+        self.set_typed_access_rights(TitleMetadataAccessRights::Wii(access_rights))
+    }
+
     /// If the title has access to the DVD drive. Only on Wii (and Wii U vWii) platform.
     pub fn has_dvd_access_wii(&self) -> Result<bool, TitleMetadataError> {
-        if let TitleMetadataPlatformData::Wii {
-            is_wii_u_vwii_only_title: _,
-            region: _,
-            ratings: _,
-            ipc_mask: _,
-        } = self.platform_data
-        {
-            return Ok((self.access_rights & 0b10) != 0);
-        }
+        Ok(self
+            .wii_access_rights()?
+            .contains(TitleMetadataWiiAccessRights::DvdAccess))
+    }
 
-        Err(TitleMetadataError::ActionInvalid())
+    /// Set if the title has access to the DVD drive. Only on Wii (and Wii U vWii) platform.
+    pub fn set_dvd_access_wii(&mut self, value: bool) -> Result<(), TitleMetadataError> {
+        self.set_wii_access_right(TitleMetadataWiiAccessRights::DvdAccess, value)
     }
 
     /// If the title has access to all hardware from its main PPC chip without using a IOS between
     /// the communication (aka disable the `AHBPROT` protection).
     /// Only on Wii (and Wii U vWii) platform.
     pub fn has_ppc_access_wii(&self) -> Result<bool, TitleMetadataError> {
+        Ok(self
+            .wii_access_rights()?
+            .contains(TitleMetadataWiiAccessRights::PpcAccess))
+    }
+
+    /// Set if the title has access to all hardware from its main PPC chip (see
+    /// [Self::has_ppc_access_wii]). Only on Wii (and Wii U vWii) platform.
+    pub fn set_ppc_access_wii(&mut self, value: bool) -> Result<(), TitleMetadataError> {
+        self.set_wii_access_right(TitleMetadataWiiAccessRights::PpcAccess, value)
+    }
+
+    /// If the title is a version of the [`boot2` bootloader](https://wiibrew.org/wiki/Boot2)
+    /// (title ID: `00000001-00000001`). Only on Wii (and Wii U vWii) platform.
+    pub fn is_boot2_wii(&self) -> Result<bool, TitleMetadataError> {
         if let TitleMetadataPlatformData::Wii {
             is_wii_u_vwii_only_title: _,
             region: _,
@@ -330,12 +431,175 @@ impl TitleMetadata {
             ipc_mask: _,
         } = self.platform_data
         {
-            return Ok((self.access_rights & 0b1) != 0);
+            return Ok(self.title_id.inner() == Self::BOOT2_TITLE_ID);
         }
 
         Err(TitleMetadataError::ActionInvalid())
     }
 
+    /// Change the IOS the title runs on (its system runtime), `ios` must be the title ID of an IOS
+    /// (see [TitleId::is_ios_wii]). Only on Wii (and Wii U vWii) platform.
+    ///
+    /// The change is made anyway if the title is itself an IOS (IOSes usually do not have a
+    /// system runtime), but a warning will be returned.
+    pub fn set_system_runtime_ios_wii(
+        &mut self,
+        ios: TitleId,
+    ) -> Result<Option<TitleMetadataSystemRuntimeWarning>, TitleMetadataError> {
+        if !matches!(self.platform_data, TitleMetadataPlatformData::Wii { .. }) {
+            return Err(TitleMetadataError::ActionInvalid());
+        }
+
+        if !ios.is_ios_wii() {
+            return Err(TitleMetadataError::NotAnIosTitleId(ios.inner()));
+        }
+
+        self.system_runtime_title_id = Some(ios);
+
+        if self.title_id.is_ios_wii() {
+            return Ok(Some(TitleMetadataSystemRuntimeWarning::TitleIsAnIos));
+        }
+
+        Ok(None)
+    }
+
+    /// Either if the title metadata was issued for development consoles.
+    pub fn is_dev(&self) -> bool {
+        self.signed_blob_header.is_dev()
+    }
+
+    /// Fakesign the title metadata: its signature will be zeroed and
+    /// [Self::title_minor_version] will be changed until the SHA-1 hash of the signed data starts
+    /// with a zero byte, making it pass the signature check on systems affected by the
+    /// ["Trucha bug"](https://wiibrew.org/wiki/Signing_bug).
+    ///
+    /// The title metadata must be fakesigned again after any modification (including the
+    /// changes made to the content entries while editing a WAD).
+    pub fn fakesign(&mut self) -> Result<(), TitleMetadataError> {
+        self.fakesign_parallel(NonZeroUsize::MIN)
+    }
+
+    /// Like [Self::fakesign] but the search will be split between the given number of threads,
+    /// the result will be the same.
+    pub fn fakesign_parallel(
+        &mut self,
+        number_of_threads: NonZeroUsize,
+    ) -> Result<(), TitleMetadataError> {
+        self.signed_blob_header.signature.clear();
+
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+
+        self.title_minor_version = signed_blob_header::trucha_brute_force(
+            &signed_data,
+            Self::FAKESIGN_FIELD_OFFSET,
+            number_of_threads,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sign the title metadata with the given private key, the issuer is the path of the certificate of the
+    /// key (like `Root-CA00000001-CP00000004`).
+    pub fn sign_with(
+        &mut self,
+        private_key: &PrivateKey,
+        issuer: &str,
+    ) -> Result<(), TitleMetadataError> {
+        self.signed_blob_header = SignedBlobHeader::new_unsigned(private_key, issuer)?;
+
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+        self.signed_blob_header.sign(&signed_data, private_key)?;
+
+        Ok(())
+    }
+
+    /// Verify the signature of the title metadata using the key of its issuer stored on the given
+    /// certificate chain.
+    pub fn verify_signature(
+        &self,
+        certificate_chain: &CertificateChain,
+    ) -> Result<SignedBlobHeaderVerification, TitleMetadataError> {
+        let signed_data = self
+            .signed_blob_header
+            .signed_data(self.size() as usize, |stream| self.dump(stream))?;
+
+        Ok(self
+            .signed_blob_header
+            .verify(&signed_data, certificate_chain)?)
+    }
+
+    /// Rebuild the content entries groups of the V1 extension (and their hashes) from
+    /// [Self::content_chunk_entries]. The current groups are kept if they still cover all the
+    /// content entries, if not all the entries will be stored on a single group. Does nothing on
+    /// title metadata without the V1 extension.
+    ///
+    /// It's done automatically after modifying the contents of a WAD.
+    pub fn rehash(&mut self) {
+        let Some(version_1_extension) = &mut self.version_1_extension else {
+            return;
+        };
+
+        let content_entries = self.content_chunk_entries.as_slice();
+
+        if !version_1_extension.covers_content_entries(content_entries.len()) {
+            version_1_extension.content_entries_groups =
+                [TitleMetadataV1ContentEntriesGroup::new_dummy(); 64];
+
+            version_1_extension.content_entries_groups[0].content_entries_in_the_group =
+                content_entries.len() as u16;
+        }
+
+        for group in &mut version_1_extension.content_entries_groups {
+            if let Some(hash) = group.hash_content_entries(content_entries) {
+                group.content_entries_group_hash_sha256 = hash;
+            }
+        }
+
+        version_1_extension.content_entries_groups_hash_sha256 = version_1_extension.groups_hash();
+    }
+
+    /// Check the content entries groups of the V1 extension (and their hashes) against
+    /// [Self::content_chunk_entries], all the mismatches found will be returned. Title metadata
+    /// without the V1 extension have no mismatches.
+    pub fn verify_v1_hashes(&self) -> Vec<TitleMetadataV1Mismatch> {
+        let mut mismatches = vec![];
+
+        let Some(version_1_extension) = &self.version_1_extension else {
+            return mismatches;
+        };
+
+        if version_1_extension.content_entries_groups_hash_sha256
+            != version_1_extension.groups_hash()
+        {
+            mismatches.push(TitleMetadataV1Mismatch::ContentEntriesGroupsHash);
+        }
+
+        for (i, group) in version_1_extension
+            .content_entries_groups
+            .iter()
+            .enumerate()
+        {
+            match group.hash_content_entries(&self.content_chunk_entries) {
+                Some(hash) if hash != group.content_entries_group_hash_sha256 => {
+                    mismatches.push(TitleMetadataV1Mismatch::ContentEntriesGroupHash(i));
+                }
+
+                Some(_) => (),
+                None => mismatches.push(TitleMetadataV1Mismatch::ContentEntriesGroupOutOfRange(i)),
+            }
+        }
+
+        if !version_1_extension.covers_content_entries(self.content_chunk_entries.len()) {
+            mismatches.push(TitleMetadataV1Mismatch::UngroupedContentEntries);
+        }
+
+        mismatches
+    }
+
     /// Get the sizes of the title metadata in bytes.
     pub fn size(&self) -> u32 {
         let num_of_entries = self.content_chunk_entries.len() as u32;
@@ -412,9 +676,6 @@ pub enum TitleMetadataError {
     )]
     UnknownWiiRegion(u16),
 
-    #[error("The given content entry kind is not known: {0}")]
-    UnknownContentEntryKind(u16),
-
     #[error("The action is invalid for the platform of the title")]
     ActionInvalid(),
 
@@ -423,6 +684,17 @@ pub enum TitleMetadataError {
 
     #[error("Content not found")]
     ContentNotFound(),
+
+    #[error("The given title ID is not the one of an IOS: {0:016X}")]
+    NotAnIosTitleId(u64),
+}
+
+/// Warnings raised while changing the system runtime of a title (see
+/// [TitleMetadata::set_system_runtime_ios_wii]), the change is made anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleMetadataSystemRuntimeWarning {
+    /// The title is itself an IOS.
+    TitleIsAnIos,
 }
 
 #[derive(Debug)]
@@ -442,9 +714,8 @@ pub enum TitleMetadataPlatformData {
         /// The region of the title
         region: TitleMetadataPlatformDataWiiRegion,
 
-        /// The "ratings" of the title.
-        // TODO(DISCOVER)
-        ratings: [u8; 16],
+        /// The age ratings of the title, used by the parental controls.
+        ratings: TitleMetadataWiiRatings,
 
         /// The IPC mask of the title.
         // TODO(DISCOVER)
@@ -475,7 +746,7 @@ impl TitleMetadataPlatformData {
             1 => Ok(Self::Wii {
                 is_wii_u_vwii_only_title: false,
                 region: TitleMetadataPlatformDataWiiRegion::RegionFree,
-                ratings: [0; 16],
+                ratings: TitleMetadataWiiRatings::from_bytes([0; 16]),
                 ipc_mask: [0; 12],
             }),
             64 => Ok(Self::Console3ds {
@@ -513,6 +784,52 @@ impl TitleMetadataPlatformData {
     }
 }
 
+/// Typed view of the access rights of a title (see [TitleMetadata::typed_access_rights]), their
+/// meaning depends on the platform of the title. Unknown bits are kept as is.
+///
+/// Only the access rights of the Nintendo Wii are known, the ones of the other platforms are
+/// exposed as their raw value until their meaning is documented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleMetadataAccessRights {
+    /// The access rights of a Nintendo DSi title, their meaning is still unknown.
+    // TODO(DISCOVER)
+    DSi(u32),
+
+    /// The access rights of a Nintendo Wii (or Wii U vWii) title.
+    Wii(TitleMetadataWiiAccessRights),
+
+    /// The access rights of a Nintendo 3DS title, their meaning is still unknown.
+    // TODO(DISCOVER)
+    Console3ds(u32),
+
+    /// The access rights of a Nintendo Wii U title, their meaning is still unknown.
+    // TODO(DISCOVER)
+    WiiU(u32),
+}
+
+impl TitleMetadataAccessRights {
+    /// Get the raw value of the access rights.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::DSi(bits) | Self::Console3ds(bits) | Self::WiiU(bits) => *bits,
+            Self::Wii(access_rights) => access_rights.bits(),
+        }
+    }
+}
+
+bitflags! {
+    /// The access rights of a Nintendo Wii (or Wii U vWii) title.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TitleMetadataWiiAccessRights: u32 {
+        /// The title has access to all hardware from its main PPC chip without using a IOS
+        /// between the communication (aka disable the `AHBPROT` protection).
+        const PpcAccess = 1 << 0;
+
+        /// The title has access to the DVD drive.
+        const DvdAccess = 1 << 1;
+    }
+}
+
 /// The different regions a title can be on a Wii console.
 #[derive(Debug)]
 #[allow(missing_docs)]
@@ -579,27 +896,64 @@ pub enum TitleMetadataContentEntryHashKind {
     Version1([u8; 32]),
 }
 
-#[derive(Clone, Copy, Debug)]
-/// The kind (behaviour of the content inside the system) of the content.
-pub enum TitleMetadataContentEntryKind {
-    /// A normal content.
-    Normal,
+bitflags! {
+    /// The kind (behaviour of the content inside the system) of the content. The meaning of
+    /// some flags depends on the platform of the title, unknown flags are kept as is.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct TitleMetadataContentEntryKind: u16 {
+        /// A normal content, set on almost all contents. On the Nintendo 3DS it means that the
+        /// content is encrypted.
+        const Normal = 0x0001;
+
+        /// The data of the content is stored along a tree of hashes (Nintendo Wii U), or the
+        /// content is stored on a disc (Nintendo 3DS).
+        const Hashed = 0x0002;
+
+        /// The content is a "CFM" (Nintendo 3DS).
+        // TODO(DISCOVER): What is a "CFM"?
+        const Cfm = 0x0004;
 
-    /// A normal content, present on the Wii U.
-    NormalWiiUKind1,
+        /// The hash of the content is a SHA-1 one (Nintendo Wii U).
+        const Sha1Hash = 0x2000;
 
-    /// A normal content, present on the Wii U (Stored with a different value in the metadata)
-    NormalWiiUKind2,
+        /// The content is optional, the system may not have it installed.
+        const Optional = 0x4000;
 
-    /// A normal content, present on the Wii U (Stored with a different value in the metadata)
-    NormalWiiUKind3,
+        /// A content that can be shared between different titles, the system may store then on
+        /// its internal memory for reuse.
+        const Shared = 0x8000;
+
+        /// A downloadable content for a title.
+        const Dlc = Self::Normal.bits() | Self::Optional.bits();
+    }
+}
+
+impl TitleMetadataContentEntryKind {
+    /// Either if the data of the content is stored along a tree of hashes (only present on the
+    /// Nintendo Wii U).
+    pub fn is_hashed(&self) -> bool {
+        self.contains(Self::Hashed)
+    }
+
+    /// Either if the content is optional (like the downloadable contents of the Nintendo Wii).
+    pub fn is_optional(&self) -> bool {
+        self.contains(Self::Optional)
+    }
+
+    /// Either if the content can be shared between different titles.
+    pub fn is_shared(&self) -> bool {
+        self.contains(Self::Shared)
+    }
 
-    /// A downloadable content for a title.
-    Dlc,
+    /// Either if the content is encrypted (only on the Nintendo 3DS).
+    pub fn is_encrypted_3ds(&self) -> bool {
+        self.contains(Self::Normal)
+    }
 
-    /// A content that can be shared between different title, the system may store then on its
-    /// internal memory for reuse.
-    Shared,
+    /// Either if the content is stored on a disc (only on the Nintendo 3DS).
+    pub fn is_disc_3ds(&self) -> bool {
+        self.contains(Self::Hashed)
+    }
 }
 
 impl TitleMetadataContentEntry {
@@ -607,16 +961,7 @@ impl TitleMetadataContentEntry {
         let id = stream.read_u32::<BE>()?;
         let index = stream.read_u16::<BE>()?;
 
-        let kind = match stream.read_u16::<BE>()? {
-            0x0001 => TitleMetadataContentEntryKind::Normal,
-            0x2001 => TitleMetadataContentEntryKind::NormalWiiUKind1,
-            0x2003 => TitleMetadataContentEntryKind::NormalWiiUKind2,
-            0x6003 => TitleMetadataContentEntryKind::NormalWiiUKind3,
-            0x4001 => TitleMetadataContentEntryKind::Dlc,
-            0x8001 => TitleMetadataContentEntryKind::Shared,
-
-            identifier => return Err(TitleMetadataError::UnknownContentEntryKind(identifier)),
-        };
+        let kind = TitleMetadataContentEntryKind::from_bits_retain(stream.read_u16::<BE>()?);
 
         let size = stream.read_u64::<BE>()?;
         let hash = if version_1 {
@@ -638,14 +983,7 @@ impl TitleMetadataContentEntry {
         stream.write_u32::<BE>(self.id)?;
         stream.write_u16::<BE>(self.index)?;
 
-        stream.write_u16::<BE>(match &self.kind {
-            TitleMetadataContentEntryKind::Normal => 0x0001,
-            TitleMetadataContentEntryKind::NormalWiiUKind1 => 0x2001,
-            TitleMetadataContentEntryKind::NormalWiiUKind2 => 0x2003,
-            TitleMetadataContentEntryKind::NormalWiiUKind3 => 0x6003,
-            TitleMetadataContentEntryKind::Dlc => 0x4001,
-            TitleMetadataContentEntryKind::Shared => 0x8001,
-        })?;
+        stream.write_u16::<BE>(self.kind.bits())?;
 
         stream.write_u64::<BE>(self.size)?;
 
@@ -683,6 +1021,38 @@ impl TitleMetadataV1 {
         })
     }
 
+    // Either if the groups (in order) cover all the content entries without gaps
+    fn covers_content_entries(&self, number_of_content_entries: usize) -> bool {
+        let mut next_content_index = 0;
+
+        for group in &self.content_entries_groups {
+            if group.content_entries_in_the_group == 0 {
+                continue;
+            }
+
+            if group.first_content_index as usize != next_content_index {
+                return false;
+            }
+
+            next_content_index += group.content_entries_in_the_group as usize;
+        }
+
+        next_content_index == number_of_content_entries
+    }
+
+    fn groups_hash(&self) -> [u8; 32] {
+        let mut groups = Cursor::new(Vec::new());
+
+        for content_entry_group in self.content_entries_groups {
+            #[allow(clippy::expect_used)]
+            content_entry_group
+                .dump(&mut groups)
+                .expect("Will never fail, the data is written into memory");
+        }
+
+        Sha256::digest(groups.get_ref()).into()
+    }
+
     fn dump<T: Write>(&self, mut stream: T) -> io::Result<()> {
         stream.write_all(&self.content_entries_groups_hash_sha256)?;
 
@@ -694,6 +1064,23 @@ impl TitleMetadataV1 {
     }
 }
 
+/// A mismatch between the V1 extension of a title metadata and its content entries, found by
+/// [TitleMetadata::verify_v1_hashes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleMetadataV1Mismatch {
+    /// The hash of all the content entries groups does not match.
+    ContentEntriesGroupsHash,
+
+    /// The hash of the content entries of a group (given its position) does not match.
+    ContentEntriesGroupHash(usize),
+
+    /// A group (given its position) has content entries that do not exist.
+    ContentEntriesGroupOutOfRange(usize),
+
+    /// Some content entries are not inside any group.
+    UngroupedContentEntries,
+}
+
 /// A group of content entries.
 #[derive(Copy, Clone, Debug)]
 pub struct TitleMetadataV1ContentEntriesGroup {
@@ -716,6 +1103,31 @@ impl TitleMetadataV1ContentEntriesGroup {
         }
     }
 
+    // Get the hash of the content entries inside the group, `None` if the group is out of range.
+    // Empty groups have a zeroed hash
+    fn hash_content_entries(
+        &self,
+        content_entries: &[TitleMetadataContentEntry],
+    ) -> Option<[u8; 32]> {
+        if self.content_entries_in_the_group == 0 {
+            return Some([0; 32]);
+        }
+
+        let start = self.first_content_index as usize;
+        let end = start + self.content_entries_in_the_group as usize;
+
+        let mut entries = Cursor::new(Vec::new());
+
+        for content_entry in content_entries.get(start..end)? {
+            #[allow(clippy::expect_used)]
+            content_entry
+                .dump(&mut entries)
+                .expect("Will never fail, the data is written into memory");
+        }
+
+        Some(Sha256::digest(entries.get_ref()).into())
+    }
+
     fn new<T: Read + Seek>(mut stream: T) -> Result<Self, TitleMetadataError> {
         let first_content_index = stream.read_u16::<BE>()?;
         let content_entries_in_the_group = stream.read_u16::<BE>()?;
@@ -737,3 +1149,186 @@ impl TitleMetadataV1ContentEntriesGroup {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use sha1::Sha1;
+
+    fn dump(title_metadata: &TitleMetadata) -> Vec<u8> {
+        let mut stream = Cursor::new(Vec::new());
+        title_metadata.dump(&mut stream).unwrap();
+
+        stream.into_inner()
+    }
+
+    #[test]
+    fn fakesign() {
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[
+                0x20, 0x10,
+            ])))
+            .unwrap();
+        title_metadata.fakesign().unwrap();
+
+        let fakesigned = dump(&title_metadata);
+        let signed_data_offset = title_metadata.signed_blob_header.signed_data_offset();
+
+        // RSA-2048 signature, after its kind
+        assert!(fakesigned[4..4 + 256].iter().all(|byte| *byte == 0));
+        assert_eq!(Sha1::digest(&fakesigned[signed_data_offset..])[0], 0);
+
+        title_metadata.fakesign().unwrap();
+        assert_eq!(dump(&title_metadata), fakesigned);
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[
+                0x20, 0x10,
+            ])))
+            .unwrap();
+        let certificate_chain = test_fixtures::signing_certificate_chain();
+
+        title_metadata
+            .sign_with(&test_fixtures::private_key(), "Root-CA00000001-CP00000004")
+            .unwrap();
+
+        assert_eq!(
+            title_metadata.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Authentic
+        );
+
+        // Flip a bit of the title version
+        let mut tampered = dump(&title_metadata);
+        tampered[0x1DD] ^= 1;
+        let tampered = TitleMetadata::new(Cursor::new(tampered)).unwrap();
+
+        assert_eq!(
+            tampered.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Invalid
+        );
+    }
+
+    #[test]
+    fn rehash_version_1() {
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_v1_bytes(&[0x20])))
+                .unwrap();
+
+        // The fixture has zeroed groups
+        assert!(!title_metadata.verify_v1_hashes().is_empty());
+
+        let mut entry = title_metadata.content_chunk_entries[0].clone();
+        entry.id = 1;
+        entry.index = 1;
+        title_metadata.content_chunk_entries.push(entry);
+
+        title_metadata.rehash();
+        assert!(title_metadata.verify_v1_hashes().is_empty());
+
+        // Still valid once dumped and parsed again
+        let mut title_metadata = TitleMetadata::new(Cursor::new(dump(&title_metadata))).unwrap();
+        assert!(title_metadata.verify_v1_hashes().is_empty());
+
+        title_metadata.content_chunk_entries[1].size = 0x30;
+
+        assert_eq!(
+            title_metadata.verify_v1_hashes(),
+            [TitleMetadataV1Mismatch::ContentEntriesGroupHash(0)]
+        );
+
+        title_metadata.content_chunk_entries.pop();
+
+        assert!(
+            title_metadata
+                .verify_v1_hashes()
+                .contains(&TitleMetadataV1Mismatch::UngroupedContentEntries)
+        );
+    }
+
+    #[test]
+    fn unknown_content_kind_bits_are_kept() {
+        // Offset of the kind of the first content entry
+        const KIND_OFFSET: usize = 0x1E4 + 6;
+
+        let mut bytes = test_fixtures::title_metadata_bytes(&[0x20]);
+        bytes[KIND_OFFSET..KIND_OFFSET + 2].copy_from_slice(&0x0101_u16.to_be_bytes());
+
+        let mut title_metadata = TitleMetadata::new(Cursor::new(&bytes)).unwrap();
+        let kind = title_metadata.content_chunk_entries[0].kind;
+
+        assert_eq!(kind.bits(), 0x0101);
+        assert!(kind.contains(TitleMetadataContentEntryKind::Normal));
+        assert_eq!(dump(&title_metadata), bytes);
+
+        title_metadata.content_chunk_entries[0].kind =
+            TitleMetadataContentEntryKind::from_bits_retain(0x1008)
+                | TitleMetadataContentEntryKind::Shared;
+
+        assert_eq!(
+            dump(&title_metadata)[KIND_OFFSET..KIND_OFFSET + 2],
+            0x9008_u16.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn reserved_data_round_trip() {
+        let mut bytes = test_fixtures::title_metadata_bytes(&[0x20, 0x10]);
+
+        // Reserved bytes of the Wii platform data, around the region, ratings and IPC mask
+        bytes[0x19A..0x19C].copy_from_slice(&[0xA5, 0x5A]);
+        bytes[0x1AE..0x1BA].fill(0x11);
+        bytes[0x1C6..0x1D8].fill(0x22);
+
+        let title_metadata = TitleMetadata::new(Cursor::new(&bytes)).unwrap();
+
+        assert_eq!(dump(&title_metadata), bytes);
+    }
+
+    #[test]
+    fn wii_access_rights() {
+        const UNKNOWN_BITS: u32 = 0x8000_0100;
+
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[0x10]))).unwrap();
+        title_metadata.access_rights = UNKNOWN_BITS;
+
+        assert!(!title_metadata.has_dvd_access_wii().unwrap());
+        assert!(!title_metadata.has_ppc_access_wii().unwrap());
+
+        title_metadata.set_dvd_access_wii(true).unwrap();
+        title_metadata.set_ppc_access_wii(true).unwrap();
+
+        assert!(title_metadata.has_dvd_access_wii().unwrap());
+        assert!(title_metadata.has_ppc_access_wii().unwrap());
+        assert_eq!(title_metadata.access_rights, UNKNOWN_BITS | 0b11);
+
+        let title_metadata_bytes = dump(&title_metadata);
+        let mut title_metadata = TitleMetadata::new(Cursor::new(title_metadata_bytes)).unwrap();
+
+        assert!(matches!(
+            title_metadata.typed_access_rights(),
+            TitleMetadataAccessRights::Wii(access_rights)
+                if access_rights == TitleMetadataWiiAccessRights::from_bits_retain(
+                    UNKNOWN_BITS | 0b11
+                )
+        ));
+
+        title_metadata.set_dvd_access_wii(false).unwrap();
+        assert!(!title_metadata.has_dvd_access_wii().unwrap());
+        assert_eq!(title_metadata.access_rights, UNKNOWN_BITS | 0b01);
+
+        title_metadata.set_ppc_access_wii(false).unwrap();
+        assert_eq!(title_metadata.access_rights, UNKNOWN_BITS);
+
+        // The typed access rights must match the platform of the title
+        assert!(matches!(
+            title_metadata.set_typed_access_rights(TitleMetadataAccessRights::WiiU(0)),
+            Err(TitleMetadataError::ActionInvalid())
+        ));
+        assert_eq!(title_metadata.access_rights, UNKNOWN_BITS);
+    }
+}
@@ -0,0 +1,189 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Implementation of the age ratings of the Nintendo Wii titles, used by the parental controls.
+
+/// The age ratings of a title, one per rating board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TitleMetadataWiiRatings([u8; 16]);
+
+impl TitleMetadataWiiRatings {
+    /// Create the ratings from its raw bytes, as stored in the title metadata.
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// Get the raw bytes of the ratings, as stored in the title metadata.
+    pub fn bytes(&self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Get the rating of the given rating board.
+    pub fn rating(&self, board: TitleMetadataWiiRatingBoard) -> TitleMetadataWiiRating {
+        TitleMetadataWiiRating(self.0[board.index()])
+    }
+
+    /// Replace the rating of the given rating board.
+    pub fn set_rating(
+        &mut self,
+        board: TitleMetadataWiiRatingBoard,
+        rating: TitleMetadataWiiRating,
+    ) -> &mut Self {
+        self.0[board.index()] = rating.0;
+
+        self
+    }
+}
+
+/// The age rating of a title given by a rating board. Bits without a known meaning are kept as
+/// is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TitleMetadataWiiRating(u8);
+
+impl TitleMetadataWiiRating {
+    const DISABLED_FLAG: u8 = 0b1000_0000;
+    const PENDING_FLAG: u8 = 0b0010_0000;
+    const AGE_MASK: u8 = 0b0001_1111;
+
+    /// Create a new rating from its raw byte.
+    pub fn from_byte(byte: u8) -> Self {
+        Self(byte)
+    }
+
+    /// Get the raw byte of the rating.
+    pub fn byte(&self) -> u8 {
+        self.0
+    }
+
+    /// Get the minimum age needed to launch the title.
+    pub fn age(&self) -> u8 {
+        self.0 & Self::AGE_MASK
+    }
+
+    /// Set the minimum age needed to launch the title, only the 5 lower bits of the value are
+    /// stored (up to 31 years).
+    pub fn set_age(&mut self, age: u8) -> &mut Self {
+        self.0 = (self.0 & !Self::AGE_MASK) | (age & Self::AGE_MASK);
+
+        self
+    }
+
+    /// Either if the rating is disabled (the rating board will be ignored) or not.
+    pub fn is_disabled(&self) -> bool {
+        self.0 & Self::DISABLED_FLAG != 0
+    }
+
+    /// Set if the rating is disabled.
+    pub fn set_disabled(&mut self, is_disabled: bool) -> &mut Self {
+        self.set_flag(Self::DISABLED_FLAG, is_disabled)
+    }
+
+    /// Either if the rating is still pending to be given by the rating board.
+    pub fn is_pending(&self) -> bool {
+        self.0 & Self::PENDING_FLAG != 0
+    }
+
+    /// Set if the rating is pending.
+    pub fn set_pending(&mut self, is_pending: bool) -> &mut Self {
+        self.set_flag(Self::PENDING_FLAG, is_pending)
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) -> &mut Self {
+        if value {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+
+        self
+    }
+}
+
+/// The age rating boards, each one has its own rating on a title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleMetadataWiiRatingBoard {
+    /// Computer Entertainment Rating Organization (Japan).
+    Cero,
+
+    /// Entertainment Software Rating Board (North America).
+    Esrb,
+
+    /// Unterhaltungssoftware Selbstkontrolle (Germany).
+    Usk,
+
+    /// Pan European Game Information (Europe).
+    Pegi,
+
+    /// Pan European Game Information (Finland).
+    PegiFinland,
+
+    /// Pan European Game Information (Portugal).
+    PegiPortugal,
+
+    /// British Board of Film Classification (United Kingdom).
+    Bbfc,
+
+    /// Australian Classification Board (Australia).
+    Agcb,
+
+    /// Game Rating Board (South Korea).
+    Grb,
+}
+
+impl TitleMetadataWiiRatingBoard {
+    /// Get the position of the rating of the board inside the ratings.
+    pub const fn index(&self) -> usize {
+        match self {
+            Self::Cero => 0,
+            Self::Esrb => 1,
+            Self::Usk => 3,
+            Self::Pegi => 4,
+            Self::PegiFinland => 5,
+            Self::PegiPortugal => 6,
+            Self::Bbfc => 7,
+            Self::Agcb => 8,
+            Self::Grb => 9,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rating_bits() {
+        let rating = TitleMetadataWiiRating::from_byte(0b1010_1100);
+
+        assert!(rating.is_disabled());
+        assert!(rating.is_pending());
+        assert_eq!(rating.age(), 12);
+    }
+
+    #[test]
+    fn set_rating_keeps_unknown_bits() {
+        let mut rating = TitleMetadataWiiRating::from_byte(0b0100_0011);
+        rating.set_age(18).set_pending(true).set_disabled(false);
+
+        assert_eq!(rating.byte(), 0b0111_0010);
+    }
+
+    #[test]
+    fn ratings_round_trip() {
+        let bytes = [
+            3, 7, 0x80, 12, 16, 0x20, 18, 15, 17, 12, 0, 0, 0, 0, 0, 0xFF,
+        ];
+        let mut ratings = TitleMetadataWiiRatings::from_bytes(bytes);
+
+        assert_eq!(ratings.rating(TitleMetadataWiiRatingBoard::Usk).age(), 12);
+        assert_eq!(ratings.rating(TitleMetadataWiiRatingBoard::Grb).age(), 12);
+
+        let pegi = ratings.rating(TitleMetadataWiiRatingBoard::Pegi);
+        ratings.set_rating(TitleMetadataWiiRatingBoard::Pegi, pegi);
+
+        assert_eq!(ratings.bytes(), bytes);
+    }
+}
@@ -6,8 +6,10 @@
 
 //! Implementation of the binary file format used by Nintendo to store titles without discs.
 
+pub mod backup;
 pub mod installable;
 
+use crate::wad::backup::{BackupWad, BackupWadError};
 use crate::wad::installable::{InstallableWad, InstallableWadError};
 use std::io;
 use std::io::Read;
@@ -15,6 +17,9 @@ use std::io::Seek;
 use thiserror::Error;
 
 const INSTALLABLE_WAD_MAGIC_NUMBERS: [u8; 8] = [0x00, 0x00, 0x00, 0x20, 0x49, 0x73, 0x00, 0x00];
+const BOOT2_INSTALLABLE_WAD_MAGIC_NUMBERS: [u8; 8] =
+    [0x00, 0x00, 0x00, 0x20, 0x69, 0x62, 0x00, 0x00];
+const BACKUP_WAD_MAGIC_NUMBERS: [u8; 8] = [0x00, 0x00, 0x00, 0x70, 0x42, 0x6B, 0x00, 0x01];
 
 /// Represent the different kinds of WAD files that are known to have been used on the Nintendo
 /// Wii.
@@ -23,10 +28,9 @@ pub enum Wad {
     /// WAD that stores the data needed to install a title into the system.
     Installable(InstallableWad),
 
-    // NOTE: Remember to also add a `try_backup` function
     /// Kind of WAD that was used to store encrypted data safely into the SD card, used to store
     /// channels and downloadable content (DLCs).
-    BackUp,
+    BackUp(BackupWad),
 }
 
 #[derive(Error, Debug)]
@@ -38,6 +42,9 @@ pub enum WadError {
     #[error("An error has occurred while parsing an installable Wad: {0}")]
     InstallableWadParseError(#[from] InstallableWadError),
 
+    #[error("An error has occurred while parsing a backup Wad: {0}")]
+    BackupWadParseError(#[from] BackupWadError),
+
     #[error("Unknown WAD format")]
     UnknownWadFormatError,
 
@@ -55,9 +62,13 @@ impl Wad {
         stream.rewind()?;
 
         match magic_numbers_buffer {
-            INSTALLABLE_WAD_MAGIC_NUMBERS => Ok(Self::Installable(unsafe {
-                InstallableWad::new(&mut stream)?
-            })),
+            INSTALLABLE_WAD_MAGIC_NUMBERS | BOOT2_INSTALLABLE_WAD_MAGIC_NUMBERS => {
+                Ok(Self::Installable(unsafe {
+                    InstallableWad::new(&mut stream)?
+                }))
+            }
+
+            BACKUP_WAD_MAGIC_NUMBERS => Ok(Self::BackUp(unsafe { BackupWad::new(&mut stream)? })),
 
             _ => Err(WadError::UnknownWadFormatError),
         }
@@ -72,4 +83,14 @@ impl Wad {
             _ => Err(WadError::UndesiredWadFormat),
         }
     }
+
+    /// Like [Self::new] but treats any format of WAD except the backup ones as an
+    /// error.
+    pub fn try_new_backup<T: Read + Seek>(stream: T) -> Result<BackupWad, WadError> {
+        match Self::new(stream)? {
+            Self::BackUp(backup_wad) => Ok(backup_wad),
+
+            _ => Err(WadError::UndesiredWadFormat),
+        }
+    }
 }
@@ -0,0 +1,524 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Implementation of a backup WAD file.
+
+mod content;
+mod title_metadata;
+
+use crate::ContentSelector;
+use crate::TitleMetadata;
+use crate::title_id::TitleId;
+use crate::title_metadata::TitleMetadataError;
+use aes::cipher::{BlockEncryptMut, KeyIvInit};
+use byteorder::{BE, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use thiserror::Error;
+use util::{Aes128CbcEnc, StreamPin};
+
+/// A WAD that stores a backup of a title (usually a channel or its downloadable content) made by
+/// the system into the SD card. Its data is bound to the console that made it.
+#[derive(Debug)]
+pub struct BackupWad {
+    /// The size of the header of the WAD.
+    pub header_size: u32,
+
+    /// The ID of the console (also known as "NG ID" or "device ID") that made the backup.
+    pub console_id: u32,
+
+    /// The number of savegame files stored inside the WAD. Usually zero.
+    pub number_of_save_files: u32,
+
+    /// The size of the savegame files stored inside the WAD. Usually zero.
+    pub save_files_size: u32,
+
+    /// The size of the title metadata stored inside the WAD.
+    pub title_metadata_size: u32,
+
+    /// The size of the content blobs stored inside the WAD.
+    pub content_size: u32,
+
+    /// The total size of the contents of the title, including the ones not stored inside the WAD.
+    pub total_size: u32,
+
+    /// Bitmask of the contents (given their index) that are stored inside the WAD, the least
+    /// significant bit of the first byte is the content with index zero.
+    pub included_contents: [u8; 64],
+
+    /// The ID of the title stored inside the WAD.
+    pub title_id: TitleId,
+
+    /// Trailing bytes of the header, its meaning is still unknown.
+    // TODO(DISCOVER)
+    pub reserved: [u8; 8],
+}
+
+/// Data unique to the console that made (or will read) a backup WAD.
+#[derive(Debug, Clone, Copy)]
+pub struct BackupWadConsoleData {
+    /// The ID of the console (also known as "NG ID" or "device ID").
+    pub device_id: u32,
+
+    /// The key used by the console to encrypt the data stored into the SD card.
+    pub sd_key: [u8; 16],
+
+    /// The initialization vector used by the console to encrypt the data stored into the SD card.
+    pub sd_iv: [u8; 16],
+}
+
+impl BackupWad {
+    const HEADER_SIZE: u32 = 0x70;
+    const SECTION_BOUNDARY: u64 = 64;
+    const MAX_CONTENTS: usize = 512;
+    const ENCRYPTION_BUFFER_SIZE: usize = 64 * 1024;
+
+    fn align_u64(value: u32) -> u64 {
+        util::align_to_boundary(value as u64, Self::SECTION_BOUNDARY)
+    }
+
+    /// Create a new backup Wad representation.
+    ///
+    /// # Safety
+    /// The given buffer is assumed to be from a backup WAD.
+    pub(crate) unsafe fn new<T: Read + Seek>(mut stream: T) -> Result<Self, BackupWadError> {
+        let header_size = stream.read_u32::<BE>()?;
+
+        // Skip the magic numbers ("Bk")
+        stream.seek_relative(2)?;
+
+        let format_version = stream.read_u16::<BE>()?;
+
+        if format_version != 1 {
+            return Err(BackupWadError::UnknownFormatVersion(format_version));
+        }
+
+        let console_id = stream.read_u32::<BE>()?;
+        let number_of_save_files = stream.read_u32::<BE>()?;
+        let save_files_size = stream.read_u32::<BE>()?;
+        let title_metadata_size = stream.read_u32::<BE>()?;
+        let content_size = stream.read_u32::<BE>()?;
+        let total_size = stream.read_u32::<BE>()?;
+        let included_contents = util::read_exact!(stream, 64)?;
+        let title_id = TitleId::new(stream.read_u64::<BE>()?);
+        let reserved = util::read_exact!(stream, 8)?;
+
+        Ok(Self {
+            header_size,
+            console_id,
+            number_of_save_files,
+            save_files_size,
+            title_metadata_size,
+            content_size,
+            total_size,
+            included_contents,
+            title_id,
+            reserved,
+        })
+    }
+
+    /// Write a new backup WAD into a stream given the title metadata and the decrypted data of
+    /// the contents to be stored, any content of the title not given will be marked as not
+    /// included. The contents will be encrypted with the SD key and IV of the console.
+    ///
+    /// Be aware that the given title metadata should have cohesion with the given contents, and
+    /// that only contents with an index lower than 512 can be stored.
+    pub fn export<T: Write + Seek, C: Read>(
+        stream: T,
+        title_metadata: &TitleMetadata,
+        contents: impl IntoIterator<Item = (ContentSelector, C)>,
+        console_data: &BackupWadConsoleData,
+    ) -> Result<Self, BackupWadError> {
+        let mut stream = StreamPin::new(stream)?;
+
+        let mut contents = contents
+            .into_iter()
+            .map(|(selector, data)| Ok((selector.physical_position(title_metadata)?, data)))
+            .collect::<Result<Vec<_>, BackupWadError>>()?;
+
+        // Contents must be stored following the order of the title metadata
+        contents.sort_by_key(|(physical_position, _)| *physical_position);
+
+        let mut backup_wad = Self {
+            header_size: Self::HEADER_SIZE,
+            console_id: console_data.device_id,
+            number_of_save_files: 0,
+            save_files_size: 0,
+            title_metadata_size: title_metadata.size(),
+            content_size: 0,
+            total_size: title_metadata
+                .content_chunk_entries
+                .iter()
+                .try_fold(0_u32, |acc, entry| {
+                    acc.checked_add(u32::try_from(entry.size).ok()?)
+                })
+                .ok_or(BackupWadError::SizeOverflow)?,
+            included_contents: [0; 64],
+            title_id: TitleId::new(title_metadata.title_id.inner()),
+            reserved: [0; 8],
+        };
+
+        for (physical_position, _) in &contents {
+            let entry = &title_metadata.content_chunk_entries[*physical_position];
+
+            if entry.index as usize >= Self::MAX_CONTENTS {
+                return Err(BackupWadError::ContentIndexOutOfRange(entry.index));
+            }
+
+            backup_wad.set_content_included(entry.index, true);
+            backup_wad.content_size = u32::try_from(entry.size)
+                .ok()
+                .and_then(|size| backup_wad.content_size.checked_add(size))
+                .ok_or(BackupWadError::SizeOverflow)?;
+        }
+
+        backup_wad.dump(&mut stream)?;
+
+        title_metadata.dump(&mut stream)?;
+        stream.align_zeroed(Self::SECTION_BOUNDARY)?;
+
+        for (physical_position, mut data) in contents {
+            let entry = &title_metadata.content_chunk_entries[physical_position];
+
+            Self::encrypt_content(&mut stream, &mut data, entry.size, console_data).map_err(
+                |error| match error.kind() {
+                    io::ErrorKind::InvalidData => BackupWadError::ContentSizeMismatch(entry.index),
+                    _ => BackupWadError::IoError(error),
+                },
+            )?;
+
+            stream.align_zeroed(Self::SECTION_BOUNDARY)?;
+        }
+
+        Ok(backup_wad)
+    }
+
+    // Encrypt the data of a content while copying it into the stream, the data must have exactly
+    // the given size (an `InvalidData` error is returned if not)
+    fn encrypt_content<T: Write, C: Read>(
+        mut stream: T,
+        mut data: C,
+        size: u64,
+        console_data: &BackupWadConsoleData,
+    ) -> io::Result<()> {
+        let mut encryptor =
+            Aes128CbcEnc::new(&console_data.sd_key.into(), &console_data.sd_iv.into());
+
+        let mut buffer = vec![0; Self::ENCRYPTION_BUFFER_SIZE];
+        let mut remaining = size;
+
+        while remaining > 0 {
+            let chunk_len = remaining.min(buffer.len() as u64) as usize;
+
+            data.read_exact(&mut buffer[..chunk_len])
+                .map_err(|error| match error.kind() {
+                    io::ErrorKind::UnexpectedEof => io::Error::from(io::ErrorKind::InvalidData),
+                    _ => error,
+                })?;
+
+            // AES-CBC only works with full blocks, only the last chunk may need padding
+            let padded_len = util::align_to_boundary(chunk_len as u64, 16) as usize;
+            buffer[chunk_len..padded_len].fill(0);
+
+            for block in buffer[..padded_len].chunks_exact_mut(16) {
+                encryptor.encrypt_block_mut(block.into());
+            }
+
+            stream.write_all(&buffer[..padded_len])?;
+            remaining -= chunk_len as u64;
+        }
+
+        if data.read(&mut [0])? != 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+
+        Ok(())
+    }
+
+    /// Dump into a stream.
+    pub fn dump<T: Write + Seek>(&self, stream: T) -> io::Result<()> {
+        let mut stream = StreamPin::new(stream)?;
+
+        stream.write_u32::<BE>(self.header_size)?;
+        write!(stream, "Bk")?;
+        stream.write_u16::<BE>(1)?;
+        stream.write_u32::<BE>(self.console_id)?;
+        stream.write_u32::<BE>(self.number_of_save_files)?;
+        stream.write_u32::<BE>(self.save_files_size)?;
+        stream.write_u32::<BE>(self.title_metadata_size)?;
+        stream.write_u32::<BE>(self.content_size)?;
+        stream.write_u32::<BE>(self.total_size)?;
+        stream.write_all(&self.included_contents)?;
+        self.title_id.dump(&mut stream)?;
+        stream.write_all(&self.reserved)?;
+        stream.align_zeroed(Self::SECTION_BOUNDARY)?;
+
+        Ok(())
+    }
+
+    /// Either if the content with the given index is stored inside the WAD or not.
+    pub fn is_content_included(&self, index: u16) -> bool {
+        let index = index as usize;
+
+        match self.included_contents.get(index / 8) {
+            Some(byte) => byte & (1 << (index % 8)) != 0,
+            None => false,
+        }
+    }
+
+    /// Mark the content with the given index as stored (or not) inside the WAD.
+    ///
+    /// # Panics
+    /// The index cannot be greater than 511.
+    pub fn set_content_included(&mut self, index: u16, included: bool) {
+        let index = index as usize;
+        let byte = &mut self.included_contents[index / 8];
+
+        if included {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[allow(missing_docs)]
+pub enum BackupWadError {
+    #[error("An IO error has occurred: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Title metadata error: {0}")]
+    TitleMetadataError(#[from] TitleMetadataError),
+
+    #[error("Unknown format version: {0}")]
+    UnknownFormatVersion(u16),
+
+    #[error("Title metadata entry not found")]
+    TitleMetadataEntryNotFoundError,
+
+    #[error("The content with index {0} is not stored inside the backup WAD")]
+    ContentNotIncluded(u16),
+
+    #[error("The data of the content with index {0} does not match the size of its entry")]
+    ContentSizeMismatch(u16),
+
+    #[error("The content index {0} cannot be stored inside a backup WAD (maximum is 511)")]
+    ContentIndexOutOfRange(u16),
+
+    #[error("The size of the contents does not fit inside a backup WAD")]
+    SizeOverflow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+    use std::io::Cursor;
+
+    fn backup_wad_bytes() -> Vec<u8> {
+        let mut bytes = vec![];
+        let title_metadata = test_fixtures::title_metadata_bytes(&[0x20, 0x10]);
+
+        bytes.extend_from_slice(&BackupWad::HEADER_SIZE.to_be_bytes());
+        bytes.extend_from_slice(b"Bk");
+        bytes.extend_from_slice(&1_u16.to_be_bytes());
+        // Console ID
+        bytes.extend_from_slice(&0x0403_0201_u32.to_be_bytes());
+        // Number and size of the savegame files
+        bytes.extend_from_slice(&0_u32.to_be_bytes());
+        bytes.extend_from_slice(&0_u32.to_be_bytes());
+        bytes.extend_from_slice(&(title_metadata.len() as u32).to_be_bytes());
+        // Content and total size, only the second content is included
+        bytes.extend_from_slice(&0x10_u32.to_be_bytes());
+        bytes.extend_from_slice(&0x30_u32.to_be_bytes());
+        let mut included_contents = [0; 64];
+        included_contents[0] = 0b10;
+        bytes.extend_from_slice(&included_contents);
+        bytes.extend_from_slice(&test_fixtures::TITLE_ID.to_be_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        bytes.resize(0x80, 0);
+
+        bytes.extend_from_slice(&title_metadata);
+        bytes.resize(util::align_to_boundary(bytes.len() as u64, 64) as usize, 0);
+
+        bytes.extend_from_slice(&[0xAB; 0x10]);
+        bytes.resize(util::align_to_boundary(bytes.len() as u64, 64) as usize, 0);
+
+        bytes
+    }
+
+    #[test]
+    fn parse_and_dump_round_trip() {
+        let bytes = backup_wad_bytes();
+        let mut stream = Cursor::new(&bytes);
+
+        let backup_wad = Wad::try_new_backup(&mut stream).unwrap();
+        assert!(!backup_wad.is_content_included(0));
+        assert!(backup_wad.is_content_included(1));
+
+        let title_metadata = backup_wad.title_metadata(&mut stream).unwrap();
+
+        let mut dumped = Cursor::new(vec![]);
+        let mut dumped_stream = StreamPin::new(&mut dumped).unwrap();
+        backup_wad.dump(&mut dumped_stream).unwrap();
+        title_metadata.dump(&mut dumped_stream).unwrap();
+        dumped_stream
+            .align_zeroed(BackupWad::SECTION_BOUNDARY)
+            .unwrap();
+
+        let mut content = vec![];
+        backup_wad
+            .encrypted_content_view(&mut stream, &title_metadata, title_metadata.select_last())
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        dumped_stream.write_all(&content).unwrap();
+        dumped_stream
+            .align_zeroed(BackupWad::SECTION_BOUNDARY)
+            .unwrap();
+
+        assert_eq!(dumped.into_inner(), bytes);
+    }
+
+    #[test]
+    fn export_rejects_out_of_range_index() {
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[0x10]))).unwrap();
+        title_metadata.content_chunk_entries[0].index = 512;
+
+        let selector = title_metadata.select_first();
+        let console_data = BackupWadConsoleData {
+            device_id: 0,
+            sd_key: [0; 16],
+            sd_iv: [0; 16],
+        };
+
+        let result = BackupWad::export(
+            Cursor::new(vec![]),
+            &title_metadata,
+            [(selector, &[0_u8; 0x10][..])],
+            &console_data,
+        );
+
+        assert!(matches!(
+            result,
+            Err(BackupWadError::ContentIndexOutOfRange(512))
+        ));
+    }
+
+    #[test]
+    fn export_rejects_oversized_contents() {
+        let title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[
+                u32::MAX as u64 + 1,
+            ])))
+            .unwrap();
+
+        let console_data = BackupWadConsoleData {
+            device_id: 0,
+            sd_key: [0; 16],
+            sd_iv: [0; 16],
+        };
+
+        let result = BackupWad::export(
+            Cursor::new(vec![]),
+            &title_metadata,
+            Vec::<(ContentSelector, &[u8])>::new(),
+            &console_data,
+        );
+
+        assert!(matches!(result, Err(BackupWadError::SizeOverflow)));
+    }
+
+    fn console_data() -> BackupWadConsoleData {
+        BackupWadConsoleData {
+            device_id: 0x0403_0201,
+            sd_key: [0x12; 16],
+            sd_iv: [0x34; 16],
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        // Bigger than the encryption buffer and not aligned to an AES block
+        let content_0 = (0..0x10025).map(|i| i as u8).collect::<Vec<u8>>();
+        let content_2 = [0xCD; 0x40];
+
+        let title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[
+                content_0.len() as u64,
+                0x30,
+                content_2.len() as u64,
+            ])))
+            .unwrap();
+
+        let mut stream = Cursor::new(vec![]);
+
+        let exported = BackupWad::export(
+            &mut stream,
+            &title_metadata,
+            [
+                (title_metadata.select_with_index(2), &content_2[..]),
+                (title_metadata.select_with_index(0), &content_0[..]),
+            ],
+            &console_data(),
+        )
+        .unwrap();
+
+        assert_eq!(exported.content_size, 0x10025 + 0x40);
+        assert_eq!(exported.total_size, 0x10025 + 0x30 + 0x40);
+        assert_eq!(stream.get_ref().len() % 64, 0);
+
+        stream.rewind().unwrap();
+        let backup_wad = Wad::try_new_backup(&mut stream).unwrap();
+        assert_eq!(backup_wad.console_id, 0x0403_0201);
+        assert!(backup_wad.is_content_included(0));
+        assert!(!backup_wad.is_content_included(1));
+        assert!(backup_wad.is_content_included(2));
+
+        let title_metadata = backup_wad.title_metadata(&mut stream).unwrap();
+
+        for (index, content) in [(0, &content_0[..]), (2, &content_2[..])] {
+            let mut decrypted = vec![];
+            backup_wad
+                .decrypted_content_view(
+                    &mut stream,
+                    &title_metadata,
+                    &console_data(),
+                    title_metadata.select_with_index(index),
+                )
+                .unwrap()
+                .read_to_end(&mut decrypted)
+                .unwrap();
+
+            assert_eq!(decrypted, content);
+        }
+    }
+
+    #[test]
+    fn export_rejects_mismatched_content_size() {
+        let title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[0x10]))).unwrap();
+
+        for content in [&[0_u8; 0x0F][..], &[0_u8; 0x11][..]] {
+            let result = BackupWad::export(
+                Cursor::new(vec![]),
+                &title_metadata,
+                [(title_metadata.select_first(), content)],
+                &console_data(),
+            );
+
+            assert!(matches!(
+                result,
+                Err(BackupWadError::ContentSizeMismatch(0))
+            ));
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::ContentSelector;
+use crate::TitleMetadata;
+use crate::wad::backup::{BackupWad, BackupWadConsoleData, BackupWadError};
+use std::io::{Read, Seek, SeekFrom};
+use util::{AesCbcStream, View};
+
+impl BackupWad {
+    /// Seek the stream of the WAD to the start of the desired content. Only the contents marked as
+    /// included (see [Self::is_content_included]) are stored inside the WAD.
+    pub fn seek_content<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        title_metadata: &TitleMetadata,
+        selector: ContentSelector,
+    ) -> Result<(), BackupWadError> {
+        let mut content_offset =
+            Self::align_u64(self.header_size) + Self::align_u64(self.title_metadata_size);
+
+        let position = selector.physical_position(title_metadata)?;
+
+        for (i, content_entry) in title_metadata.content_chunk_entries.iter().enumerate() {
+            let is_included = self.is_content_included(content_entry.index);
+
+            if i == position {
+                if !is_included {
+                    return Err(BackupWadError::ContentNotIncluded(content_entry.index));
+                }
+
+                stream.seek(SeekFrom::Start(content_offset))?;
+                return Ok(());
+            }
+
+            if is_included {
+                content_offset +=
+                    util::align_to_boundary(content_entry.size, Self::SECTION_BOUNDARY);
+            }
+        }
+
+        Err(BackupWadError::TitleMetadataEntryNotFoundError)
+    }
+
+    /// Create a [View] into the desired content stored inside the WAD stream. Be aware that the
+    /// stream will be only of the encrypted data, [Self::decrypted_content_view] may be prefered.
+    pub fn encrypted_content_view<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        title_metadata: &TitleMetadata,
+        selector: ContentSelector,
+    ) -> Result<View<T>, BackupWadError> {
+        self.seek_content(&mut stream, title_metadata, selector)?;
+        let entry = selector.content_entry(title_metadata)?;
+
+        Ok(View::new(stream, entry.size as usize)?)
+    }
+
+    /// Create a [View] into the desired content stored inside the WAD stream. Decryption is done
+    /// in place with the SD key and IV of the console that made the backup, be aware that **zero
+    /// caching is implemented on the [AesCbcStream] type, wrapping the stream on a
+    /// [std::io::BufReader] may be useful.
+    pub fn decrypted_content_view<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        title_metadata: &TitleMetadata,
+        console_data: &BackupWadConsoleData,
+        selector: ContentSelector,
+    ) -> Result<View<AesCbcStream<View<T>>>, BackupWadError> {
+        self.seek_content(&mut stream, title_metadata, selector)?;
+        let entry = selector.content_entry(title_metadata)?;
+
+        // The encrypted data is padded to the size of an AES block, the padding is needed to
+        // decrypt the last block
+        let content_view = View::new(stream, util::align_to_boundary(entry.size, 16) as usize)?;
+
+        let decrypted_stream =
+            AesCbcStream::new(content_view, console_data.sd_key, console_data.sd_iv)?;
+
+        Ok(View::new(decrypted_stream, entry.size as usize)?)
+    }
+}
@@ -0,0 +1,40 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::title_metadata::{TitleMetadata, TitleMetadataError};
+use crate::wad::backup::BackupWad;
+use std::io::{Read, Seek, SeekFrom};
+use util::View;
+
+impl BackupWad {
+    /// Seek the stream of the WAD to the start of the title metadata.
+    pub fn seek_title_metadata<T: Seek>(&self, mut stream: T) -> Result<(), TitleMetadataError> {
+        let title_metadata_offset = Self::align_u64(self.header_size);
+
+        stream.seek(SeekFrom::Start(title_metadata_offset))?;
+        Ok(())
+    }
+
+    /// Create a [View] into the title metadata stored inside the WAD stream.
+    pub fn title_metadata_view<T: Read + Seek>(
+        &self,
+        mut stream: T,
+    ) -> Result<View<T>, TitleMetadataError> {
+        self.seek_title_metadata(&mut stream)?;
+
+        Ok(View::new(stream, self.title_metadata_size as usize)?)
+    }
+
+    /// Parse the title metadata stored inside the WAD stream.
+    pub fn title_metadata<T: Read + Seek>(
+        &self,
+        mut stream: T,
+    ) -> Result<TitleMetadata, TitleMetadataError> {
+        self.seek_title_metadata(&mut stream)?;
+
+        TitleMetadata::new(&mut stream)
+    }
+}
@@ -6,10 +6,19 @@
 
 //! Implementation of a installable WAD file.
 
+mod builder;
 mod certificate_chain;
 mod content;
+mod directory;
+mod footer;
+mod region;
 mod ticket;
 mod title_metadata;
+mod verify;
+
+pub use builder::{WadBuilder, WadBuilderContent};
+pub use directory::WadDirectoryContentFormat;
+pub use verify::{InstallableWadSection, InstallableWadVerifyIssue, InstallableWadVerifyReport};
 
 use crate::TitleMetadata;
 use crate::certificate_chain::CertificateChainError;
@@ -49,12 +58,6 @@ pub struct InstallableWad {
     pub footer_size: u32,
 }
 
-#[derive(Debug)]
-struct ContentsStore {
-    contents: Vec<Vec<u8>>,
-    first_content_physical_position: usize,
-}
-
 impl InstallableWad {
     const HEADER_SIZE: u64 = 64;
     const SECTION_BOUNDARY: u64 = 64;
@@ -64,6 +67,14 @@ impl InstallableWad {
         util::align_to_boundary(value as u64, Self::SECTION_BOUNDARY)
     }
 
+    fn content_section_offset(&self) -> u64 {
+        // The header is always aligned to the boundary
+        Self::HEADER_SIZE
+            + Self::align_u64(self.certificate_chain_size)
+            + Self::align_u64(self.ticket_size)
+            + Self::align_u64(self.title_metadata_size)
+    }
+
     /// Create a new installable Wad representation.
     ///
     /// # Safety
@@ -103,8 +114,8 @@ impl InstallableWad {
     pub fn dump<T: Write + Seek>(&self, stream: T) -> io::Result<()> {
         let mut stream = StreamPin::new(stream)?;
 
-        stream.write_u32::<BE>(32)?;
-        write!(stream, "Is")?;
+        stream.write_u32::<BE>(self.header_size)?;
+        self.kind.dump(&mut stream)?;
         stream.write_u16::<BE>(0)?;
         stream.write_u32::<BE>(self.certificate_chain_size)?;
         stream.write_zeroed(4)?;
@@ -118,57 +129,22 @@ impl InstallableWad {
         Ok(())
     }
 
-    fn store_contents<T: Read + Write + Seek>(
-        &mut self,
+    /// Move all the data of the WAD stored from `old_offset` up to its end (as described by the
+    /// header and the given title metadata) into `new_offset`. The copy is done in place, so
+    /// memory usage does not depend on the size of the WAD.
+    fn shift_trailing_data<T: Read + Write + Seek>(
+        &self,
         mut stream: T,
         title_metadata: &TitleMetadata,
-        first_content_physical_position: usize,
-    ) -> Result<Option<ContentsStore>, InstallableWadError> {
-        let mut all_contents_bytes = vec![];
-
-        let number_of_entries = title_metadata.content_chunk_entries.len();
-
-        if number_of_entries == 0 || first_content_physical_position >= number_of_entries {
-            return Ok(None);
-        }
-
-        for i in first_content_physical_position..title_metadata.content_chunk_entries.len() {
-            let mut view = self.encrypted_content_view(
-                &mut stream,
-                title_metadata,
-                title_metadata.select_with_physical_position(i),
-            )?;
-
-            let mut content_bytes = vec![];
-            view.read_to_end(&mut content_bytes)?;
-            all_contents_bytes.push(content_bytes);
-        }
+        old_offset: u64,
+        new_offset: u64,
+    ) -> Result<(), InstallableWadError> {
+        let end_offset = self.end_offset(title_metadata);
 
-        Ok(Some(ContentsStore {
-            contents: all_contents_bytes,
-            first_content_physical_position,
-        }))
-    }
+        stream.rewind()?;
+        let mut stream = StreamPin::new(stream)?;
 
-    fn restore_contents<T: Write + Read + Seek>(
-        &mut self,
-        stream: &mut StreamPin<T>,
-        title_metadata: &TitleMetadata,
-        contents_store: &Option<ContentsStore>,
-    ) -> Result<(), InstallableWadError> {
-        if let Some(contents_store) = contents_store {
-            self.seek_content(
-                &mut *stream,
-                title_metadata,
-                title_metadata
-                    .select_with_physical_position(contents_store.first_content_physical_position),
-            )?;
-
-            for bytes in &contents_store.contents {
-                stream.write_all(bytes)?;
-                stream.align_zeroed(Self::SECTION_BOUNDARY)?;
-            }
-        };
+        stream.copy_within(old_offset, new_offset, end_offset - old_offset)?;
 
         Ok(())
     }
@@ -203,10 +179,22 @@ pub enum InstallableWadError {
 
     #[error("Unknown format version: {0}")]
     UnknownFormatVersion(u16),
+
+    #[error("The data of the content with ID {0} does not match the size of its entry")]
+    ContentSizeMismatch(u32),
+
+    #[error("The given title is not a version of boot2")]
+    NotABoot2Title,
+
+    #[error("Invalid WAD directory manifest, on line {0}")]
+    InvalidDirectoryManifest(usize),
+
+    #[error("Missing the data of the content with ID {0:08x} on the WAD directory")]
+    MissingDirectoryContent(u32),
 }
 
 /// Ways a WAD can install a title.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstallableWadKind {
     /// Install it as usual.
     Normal,
@@ -227,4 +215,53 @@ impl InstallableWadKind {
             _ => return Err(InstallableWadError::UnknownInstallableWadTypeError(bytes)),
         })
     }
+
+    /// Get the kind of installation needed by a title given its title metadata.
+    pub fn from_title_metadata(title_metadata: &TitleMetadata) -> Self {
+        match title_metadata.is_boot2_wii() {
+            Ok(true) => Self::Boot2,
+            _ => Self::Normal,
+        }
+    }
+
+    fn dump<T: Write>(&self, mut stream: T) -> io::Result<()> {
+        match self {
+            Self::Normal => write!(stream, "Is"),
+            Self::Boot2 => write!(stream, "ib"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+    use std::io::Cursor;
+
+    fn header_round_trip(magic_numbers: &[u8; 2], kind: InstallableWadKind) {
+        let mut bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+        bytes[4..6].copy_from_slice(magic_numbers);
+
+        let wad = Wad::try_new_installable(Cursor::new(&bytes)).unwrap();
+        assert_eq!(wad.kind, kind);
+
+        let mut dumped = Cursor::new(vec![]);
+        wad.dump(&mut dumped).unwrap();
+
+        assert_eq!(
+            dumped.get_ref()[..],
+            bytes[..InstallableWad::SECTION_BOUNDARY as usize]
+        );
+    }
+
+    #[test]
+    fn normal_header_round_trip() {
+        header_round_trip(b"Is", InstallableWadKind::Normal);
+    }
+
+    #[test]
+    fn boot2_header_round_trip() {
+        header_round_trip(b"ib", InstallableWadKind::Boot2);
+    }
 }
@@ -0,0 +1,445 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::CryptographicMethod;
+use crate::KeyProvider;
+use crate::certificate_chain::CertificateChain;
+use crate::title_id::TitleId;
+use crate::title_metadata::{
+    TitleMetadataContentEntry, TitleMetadataContentEntryHashKind, TitleMetadataContentEntryKind,
+};
+use crate::wad::installable::{InstallableWad, InstallableWadError, InstallableWadKind};
+use crate::{PreSwitchTicket, TitleMetadata};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::mem;
+use util::StreamPin;
+use util::WriteEx;
+
+/// The data of a content to be stored by a [WadBuilder].
+pub enum WadBuilderContent<'a> {
+    /// Decrypted data, it will be encrypted with the title key of the ticket.
+    Decrypted(Box<dyn Read + 'a>),
+
+    /// Already encrypted data, it will be stored as it is.
+    Encrypted {
+        /// The encrypted data, including its padding to the AES block size (16 bytes).
+        data: Box<dyn Read + 'a>,
+
+        /// The size of the data once decrypted.
+        size: u64,
+    },
+}
+
+struct WadBuilderContentEntry<'a> {
+    id: u32,
+    index: u16,
+    kind: TitleMetadataContentEntryKind,
+    content: WadBuilderContent<'a>,
+}
+
+/// Builder to assemble an installable WAD from scratch, create one with [WadBuilder::new].
+///
+/// The content entries of the given title metadata will be replaced with the added contents, its
+/// hashes and sizes will be computed while building.
+///
+/// If the title is a version of boot2 the WAD will use the [InstallableWadKind::Boot2] kind and
+/// the system runtime of the title metadata will be set to its own title ID.
+pub struct WadBuilder<'a> {
+    certificate_chain: &'a CertificateChain,
+    ticket: &'a PreSwitchTicket,
+    title_metadata: &'a mut TitleMetadata,
+    cryptographic_method: CryptographicMethod,
+    key_provider: &'a dyn KeyProvider,
+    kind: Option<InstallableWadKind>,
+    contents: Vec<WadBuilderContentEntry<'a>>,
+    footer: Option<&'a [u8]>,
+}
+
+impl<'a> WadBuilder<'a> {
+    /// Create a new [WadBuilder].
+    pub fn new(
+        certificate_chain: &'a CertificateChain,
+        ticket: &'a PreSwitchTicket,
+        title_metadata: &'a mut TitleMetadata,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &'a dyn KeyProvider,
+    ) -> Self {
+        Self {
+            certificate_chain,
+            ticket,
+            title_metadata,
+            cryptographic_method,
+            key_provider,
+            kind: None,
+            contents: vec![],
+            footer: None,
+        }
+    }
+
+    /// Add a new content at the end of the WAD.
+    pub fn add_content(
+        &mut self,
+        id: u32,
+        index: u16,
+        kind: TitleMetadataContentEntryKind,
+        content: WadBuilderContent<'a>,
+    ) -> &mut Self {
+        self.contents.push(WadBuilderContentEntry {
+            id,
+            index,
+            kind,
+            content,
+        });
+
+        self
+    }
+
+    /// Set the kind of installation of the WAD, by default is inferred from the title metadata.
+    pub fn set_kind(&mut self, kind: InstallableWadKind) -> &mut Self {
+        self.kind = Some(kind);
+
+        self
+    }
+
+    /// Set the footer to be stored at the end of the WAD.
+    pub fn set_footer(&mut self, footer: &'a [u8]) -> &mut Self {
+        self.footer = Some(footer);
+
+        self
+    }
+
+    /// Write the WAD into a stream. All the added contents will be consumed.
+    pub fn build<T: Write + Seek>(
+        &mut self,
+        stream: T,
+    ) -> Result<InstallableWad, InstallableWadError> {
+        let mut stream = StreamPin::new(stream)?;
+        let contents = mem::take(&mut self.contents);
+        let footer = self.footer.unwrap_or_default();
+
+        let kind = self
+            .kind
+            .unwrap_or_else(|| InstallableWadKind::from_title_metadata(self.title_metadata));
+
+        if kind == InstallableWadKind::Boot2 {
+            if !self.title_metadata.is_boot2_wii()? {
+                return Err(InstallableWadError::NotABoot2Title);
+            }
+
+            // boot2 uses its own title ID as its system runtime
+            self.title_metadata.system_runtime_title_id =
+                Some(TitleId::new(self.title_metadata.title_id.inner()));
+        }
+
+        // The size of the title metadata only depends on its number of entries, so they can be
+        // filled now and updated once each content is written
+        self.title_metadata.content_chunk_entries = contents
+            .iter()
+            .map(|entry| TitleMetadataContentEntry {
+                id: entry.id,
+                index: entry.index,
+                kind: entry.kind,
+                size: 0,
+                hash: if self.title_metadata.version_1_extension.is_some() {
+                    TitleMetadataContentEntryHashKind::Version1([0; 32])
+                } else {
+                    TitleMetadataContentEntryHashKind::Version0([0; 20])
+                },
+            })
+            .collect();
+
+        let mut wad = InstallableWad {
+            header_size: 32,
+            kind,
+            certificate_chain_size: self.certificate_chain.size(),
+            ticket_size: self.ticket.size(),
+            title_metadata_size: self.title_metadata.size(),
+            content_size: 0,
+            footer_size: footer.len() as u32,
+        };
+
+        wad.dump(&mut stream)?;
+
+        self.certificate_chain.dump(&mut stream)?;
+        stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
+
+        self.ticket.dump(&mut stream)?;
+        stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
+
+        // Leave room for the title metadata
+        let title_metadata_position = stream.relative_position()?;
+        stream.write_zeroed(InstallableWad::align_u64(wad.title_metadata_size) as usize)?;
+
+        for (i, entry) in contents.into_iter().enumerate() {
+            let selector = self.title_metadata.select_with_physical_position(i);
+
+            let decrypted_data = match entry.content {
+                WadBuilderContent::Decrypted(mut data) => {
+                    let mut data_vec = vec![];
+                    data.read_to_end(&mut data_vec)?;
+
+                    // AES-CBC only works with full blocks, pad in place and drop the padding
+                    // once encrypted
+                    let size = data_vec.len();
+                    data_vec.resize(util::align_to_boundary(size as u64, 16) as usize, 0);
+
+                    let mut encrypted_stream = self.ticket.cryptographic_stream(
+                        &mut stream,
+                        self.title_metadata,
+                        selector,
+                        self.cryptographic_method,
+                        self.key_provider,
+                    )?;
+
+                    encrypted_stream.write_all(&data_vec)?;
+                    data_vec.truncate(size);
+
+                    data_vec
+                }
+
+                WadBuilderContent::Encrypted { mut data, size } => {
+                    let mut data_vec = vec![];
+                    data.read_to_end(&mut data_vec)?;
+
+                    if data_vec.len() as u64 != util::align_to_boundary(size, 16) {
+                        return Err(InstallableWadError::ContentSizeMismatch(entry.id));
+                    }
+
+                    stream.write_all(&data_vec)?;
+
+                    let mut decrypted_stream = self.ticket.cryptographic_stream(
+                        Cursor::new(data_vec),
+                        self.title_metadata,
+                        selector,
+                        self.cryptographic_method,
+                        self.key_provider,
+                    )?;
+
+                    let mut decrypted_data = vec![0; size as usize];
+                    decrypted_stream.read_exact(&mut decrypted_data)?;
+
+                    decrypted_data
+                }
+            };
+
+            stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
+
+            let title_metadata_entry = &mut self.title_metadata.content_chunk_entries[i];
+
+            title_metadata_entry.size = decrypted_data.len() as u64;
+            title_metadata_entry.hash = match title_metadata_entry.hash {
+                TitleMetadataContentEntryHashKind::Version0(_) => {
+                    TitleMetadataContentEntryHashKind::Version0(
+                        Sha1::digest(&decrypted_data).into(),
+                    )
+                }
+
+                TitleMetadataContentEntryHashKind::Version1(_) => {
+                    TitleMetadataContentEntryHashKind::Version1(
+                        Sha256::digest(&decrypted_data).into(),
+                    )
+                }
+            };
+
+            wad.content_size += decrypted_data.len() as u32;
+        }
+
+        stream.write_all(footer)?;
+        stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
+
+        let end_position = stream.stream_position()?;
+
+        stream.go_to_pin()?;
+        wad.dump(&mut stream)?;
+
+        self.title_metadata.rehash();
+
+        stream.seek_from_pin(title_metadata_position)?;
+        self.title_metadata.dump(&mut stream)?;
+
+        // Leave the stream at the end of the WAD
+        stream.seek(SeekFrom::Start(end_position))?;
+
+        Ok(wad)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedKeyProvider;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+
+    const BOOT2_TITLE_ID: u64 = 0x0000_0001_0000_0001;
+
+    fn build(
+        ticket: &PreSwitchTicket,
+        title_metadata: &mut TitleMetadata,
+        content: WadBuilderContent,
+        footer: &[u8],
+    ) -> Result<Vec<u8>, InstallableWadError> {
+        let certificate_chain = test_fixtures::certificate_chain();
+        let mut stream = Cursor::new(vec![]);
+
+        WadBuilder::new(
+            &certificate_chain,
+            ticket,
+            title_metadata,
+            CryptographicMethod::Wii,
+            &EmbeddedKeyProvider,
+        )
+        .add_content(0x10, 0, TitleMetadataContentEntryKind::Normal, content)
+        .set_footer(footer)
+        .build(&mut stream)?;
+
+        Ok(stream.into_inner())
+    }
+
+    fn assert_verified(bytes: &[u8]) -> InstallableWad {
+        let mut stream = Cursor::new(bytes);
+        let wad = Wad::try_new_installable(&mut stream).unwrap();
+
+        let report = wad
+            .verify(&mut stream, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+
+        wad
+    }
+
+    fn ticket() -> PreSwitchTicket {
+        PreSwitchTicket::new(Cursor::new(test_fixtures::ticket_bytes())).unwrap()
+    }
+
+    #[test]
+    fn build_with_footer() {
+        let bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+        let wad = assert_verified(&bytes);
+
+        assert_eq!(wad.kind, InstallableWadKind::Normal);
+        assert_eq!(&bytes[4..6], b"Is");
+        assert_eq!(wad.footer_size, 0x21);
+        assert_eq!(bytes.len() % 64, 0);
+
+        let mut stream = Cursor::new(&bytes);
+        let title_metadata = wad.title_metadata(&mut stream).unwrap();
+        assert_eq!(
+            wad.footer(&mut stream, &title_metadata).unwrap(),
+            [0xEF; 0x21]
+        );
+
+        for (i, entry) in title_metadata.content_chunk_entries.iter().enumerate() {
+            assert_eq!(entry.index, i as u16);
+            assert_eq!(
+                entry.size,
+                test_fixtures::installable_wad_content(i as u16).len() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn build_boot2() {
+        let mut ticket = ticket();
+        ticket.title_id = TitleId::new(BOOT2_TITLE_ID);
+
+        let mut bytes = test_fixtures::title_metadata_bytes(&[]);
+        bytes[0x18C..0x194].copy_from_slice(&BOOT2_TITLE_ID.to_be_bytes());
+        let mut title_metadata = TitleMetadata::new(Cursor::new(bytes)).unwrap();
+
+        let bytes = build(
+            &ticket,
+            &mut title_metadata,
+            WadBuilderContent::Decrypted(Box::new(&[0x12; 0x30][..])),
+            &[],
+        )
+        .unwrap();
+        let wad = assert_verified(&bytes);
+
+        assert_eq!(wad.kind, InstallableWadKind::Boot2);
+        assert_eq!(&bytes[4..6], b"ib");
+
+        let title_metadata = wad.title_metadata(Cursor::new(&bytes)).unwrap();
+        assert_eq!(
+            title_metadata
+                .system_runtime_title_id
+                .map(|title_id| title_id.inner()),
+            Some(BOOT2_TITLE_ID)
+        );
+    }
+
+    #[test]
+    fn build_rehashes_version_1() {
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_v1_bytes(&[]))).unwrap();
+
+        let bytes = build(
+            &ticket(),
+            &mut title_metadata,
+            WadBuilderContent::Decrypted(Box::new(&[0x12; 0x30][..])),
+            &[],
+        )
+        .unwrap();
+        let wad = assert_verified(&bytes);
+
+        let title_metadata = wad.title_metadata(Cursor::new(&bytes)).unwrap();
+        assert!(title_metadata.version_1_extension.is_some());
+        assert!(title_metadata.verify_v1_hashes().is_empty());
+    }
+
+    #[test]
+    fn build_encrypted_size_mismatch() {
+        for encrypted_size in [0x20, 0x31] {
+            let mut title_metadata =
+                TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[]))).unwrap();
+
+            let result = build(
+                &ticket(),
+                &mut title_metadata,
+                WadBuilderContent::Encrypted {
+                    data: Box::new(Cursor::new(vec![0; encrypted_size])),
+                    size: 0x25,
+                },
+                &[],
+            );
+
+            assert!(matches!(
+                result,
+                Err(InstallableWadError::ContentSizeMismatch(0x10))
+            ));
+        }
+    }
+
+    #[test]
+    fn build_encrypted() {
+        let bytes = test_fixtures::installable_wad_bytes(&[]);
+        let mut stream = Cursor::new(&bytes);
+        let wad = Wad::try_new_installable(&mut stream).unwrap();
+        let title_metadata = wad.title_metadata(&mut stream).unwrap();
+
+        let mut encrypted = vec![0; 0x30];
+        wad.seek_content(&mut stream, &title_metadata, title_metadata.select_first())
+            .unwrap();
+        stream.read_exact(&mut encrypted).unwrap();
+
+        let mut title_metadata =
+            TitleMetadata::new(Cursor::new(test_fixtures::title_metadata_bytes(&[]))).unwrap();
+        let rebuilt = build(
+            &ticket(),
+            &mut title_metadata,
+            WadBuilderContent::Encrypted {
+                data: Box::new(Cursor::new(encrypted)),
+                size: 0x25,
+            },
+            &[],
+        )
+        .unwrap();
+
+        assert_verified(&rebuilt);
+    }
+}
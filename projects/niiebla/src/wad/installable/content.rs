@@ -6,6 +6,7 @@
 
 use crate::ContentSelector;
 use crate::CryptographicMethod;
+use crate::KeyProvider;
 use crate::title_metadata::{
     TitleMetadataContentEntry, TitleMetadataContentEntryHashKind, TitleMetadataContentEntryKind,
 };
@@ -20,25 +21,18 @@ use util::AesCbcStream;
 use util::{StreamPin, View};
 
 impl InstallableWad {
-    /// Seek the stream of the WAD to the start of the desired content.
-    pub fn seek_content<T: Read + Seek>(
+    pub(super) fn content_offset(
         &self,
-        mut stream: T,
         title_metadata: &TitleMetadata,
         selector: ContentSelector,
-    ) -> Result<(), InstallableWadError> {
-        // The header is always aligned to the boundary
-        let mut content_offset = Self::HEADER_SIZE
-            + Self::align_u64(self.certificate_chain_size)
-            + Self::align_u64(self.ticket_size)
-            + Self::align_u64(self.title_metadata_size);
+    ) -> Result<u64, InstallableWadError> {
+        let mut content_offset = self.content_section_offset();
 
         let position = selector.physical_position(title_metadata)?;
 
         for (i, content_entry) in title_metadata.content_chunk_entries.iter().enumerate() {
             if i == position {
-                stream.seek(SeekFrom::Start(content_offset))?;
-                return Ok(());
+                return Ok(content_offset);
             }
 
             content_offset += util::align_to_boundary(content_entry.size, Self::SECTION_BOUNDARY);
@@ -47,6 +41,19 @@ impl InstallableWad {
         Err(InstallableWadError::TitleMetadataEntryNotFoundError)
     }
 
+    /// Seek the stream of the WAD to the start of the desired content.
+    pub fn seek_content<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        title_metadata: &TitleMetadata,
+        selector: ContentSelector,
+    ) -> Result<(), InstallableWadError> {
+        let content_offset = self.content_offset(title_metadata, selector)?;
+
+        stream.seek(SeekFrom::Start(content_offset))?;
+        Ok(())
+    }
+
     /// Create a [View] into the desired content stored inside the WAD stream. Be aware that the
     /// stream will be only of the encrypted data, [Self::decrypted_content_view] may be prefered.
     pub fn encrypted_content_view<T: Read + Seek>(
@@ -66,20 +73,29 @@ impl InstallableWad {
     /// the stream on a [std::io::BufReader] may be useful.
     pub fn decrypted_content_view<T: Read + Seek>(
         &self,
-        stream: T,
+        mut stream: T,
         ticket: &PreSwitchTicket,
         title_metadata: &TitleMetadata,
         cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
         selector: ContentSelector,
-    ) -> Result<AesCbcStream<View<T>>, InstallableWadError> {
-        let content_view = self.encrypted_content_view(stream, title_metadata, selector)?;
+    ) -> Result<View<AesCbcStream<View<T>>>, InstallableWadError> {
+        self.seek_content(&mut stream, title_metadata, selector)?;
+        let entry = selector.content_entry(title_metadata)?;
+
+        // The encrypted data is padded to the size of an AES block, the padding is needed to
+        // decrypt the last block
+        let content_view = View::new(stream, util::align_to_boundary(entry.size, 16) as usize)?;
 
-        Ok(ticket.cryptographic_stream(
+        let decrypted_stream = ticket.cryptographic_stream(
             content_view,
             title_metadata,
             selector,
             cryptographic_method,
-        )?)
+            key_provider,
+        )?;
+
+        Ok(View::new(decrypted_stream, entry.size as usize)?)
     }
 
     /// Get a builder to modify the contents stored in the WAD.
@@ -95,6 +111,7 @@ impl InstallableWad {
             new_kind: None,
             ticket: None,
             cryptographic_method: None,
+            key_provider: None,
             trim_if_is_file: false,
         }
     }
@@ -108,6 +125,7 @@ pub struct ModifyContentBuilder<'a, 'b, 'c, T: Read + Write + Seek + Any> {
     new_kind: Option<TitleMetadataContentEntryKind>,
     ticket: Option<&'c PreSwitchTicket>,
     cryptographic_method: Option<CryptographicMethod>,
+    key_provider: Option<&'c dyn KeyProvider>,
     trim_if_is_file: bool,
 }
 
@@ -116,9 +134,11 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
         &mut self,
         ticket: &'c PreSwitchTicket,
         crytographic_method: CryptographicMethod,
+        key_provider: &'c dyn KeyProvider,
     ) -> &mut Self {
         self.ticket = Some(ticket);
         self.cryptographic_method = Some(crytographic_method);
+        self.key_provider = Some(key_provider);
 
         self
     }
@@ -162,6 +182,18 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
         Ok(())
     }
 
+    fn trim_file_if_needed(&mut self) -> Result<(), InstallableWadError> {
+        if self.trim_if_is_file
+            && let Some(file) = (self.wad_stream as &mut dyn Any).downcast_mut::<File>()
+        {
+            let len = file.stream_position()?;
+
+            file.set_len(len)?;
+        }
+
+        Ok(())
+    }
+
     #[allow(clippy::expect_used)]
     pub fn add<S: Read + Write + Seek>(
         &mut self,
@@ -188,13 +220,18 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
             .cryptographic_method
             .expect("Missing cryptographic method, use `.set_cryptography()` on the builder");
 
+        let key_provider = self
+            .key_provider
+            .expect("Missing key provider, use `.set_cryptography()` on the builder");
+
+        self.wad_stream.rewind()?;
         let mut wad_stream = StreamPin::new(&mut self.wad_stream)?;
         let content_selector = title_metadata.select_last();
 
-        self.wad
-            .seek_content(&mut wad_stream, title_metadata, content_selector)?;
-        wad_stream.seek_relative(content_selector.content_entry(title_metadata)?.size as i64)?;
-        wad_stream.align_position(InstallableWad::SECTION_BOUNDARY)?;
+        // The new content will be placed where the footer is stored
+        let footer = self.wad.footer(&mut wad_stream, title_metadata)?;
+
+        self.wad.seek_footer(&mut wad_stream, title_metadata)?;
 
         let mut new_data_vec = vec![];
         new_data.read_to_end(&mut new_data_vec)?;
@@ -215,21 +252,35 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
 
         title_metadata.content_chunk_entries.push(entry);
 
+        // AES-CBC only works with full blocks
+        new_data_vec.resize(
+            util::align_to_boundary(new_data_vec.len() as u64, 16) as usize,
+            0,
+        );
+
         let mut wad_stream = ticket.cryptographic_stream(
             &mut wad_stream,
             title_metadata,
             content_selector,
             cryptographic_method,
+            key_provider,
         )?;
 
         wad_stream.write(&new_data_vec)?;
 
+        let wad_stream = wad_stream.into_inner();
+        wad_stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
+        wad_stream.write_all(&footer)?;
+        wad_stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
+
         // Modifing the title metadata must be done at the end to avoid issues with the position of
         // the stream (writing on the start of the WAD by accident)
-        let mut wad_stream = wad_stream.into_inner();
+        title_metadata.rehash();
+
         self.wad
-            .write_title_metadata_safe(&mut wad_stream, title_metadata)?;
+            .write_title_metadata_safe(&mut *wad_stream, title_metadata)?;
 
+        self.trim_file_if_needed()?;
         self.sync_wad_header_content_size(title_metadata)?;
 
         Ok(())
@@ -240,35 +291,35 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
         content_selector: ContentSelector,
         title_metadata: &mut TitleMetadata,
     ) -> Result<(), InstallableWadError> {
+        self.wad_stream.rewind()?;
         let mut wad_stream = StreamPin::new(&mut self.wad_stream)?;
         let physical_position = content_selector.physical_position(title_metadata)?;
 
-        let mut contents =
-            self.wad
-                .store_contents(&mut wad_stream, title_metadata, physical_position + 1)?;
+        let content_offset = self.wad.content_offset(title_metadata, content_selector)?;
+        let content_end_offset = content_offset
+            + util::align_to_boundary(
+                content_selector.content_entry(title_metadata)?.size,
+                InstallableWad::SECTION_BOUNDARY,
+            );
 
-        if let Some(ref mut contents) = contents {
-            contents.first_content_physical_position -= 1;
-        }
+        // Overwrite the content with all the data stored after it
+        self.wad.shift_trailing_data(
+            &mut wad_stream,
+            title_metadata,
+            content_end_offset,
+            content_offset,
+        )?;
 
         title_metadata
             .content_chunk_entries
             .remove(physical_position);
 
-        self.wad
-            .write_title_metadata_safe(&mut wad_stream, title_metadata)?;
+        title_metadata.rehash();
 
         self.wad
-            .restore_contents(&mut wad_stream, title_metadata, &contents)?;
-
-        if self.trim_if_is_file
-            && let Some(file) = (self.wad_stream as &mut dyn Any).downcast_mut::<File>()
-        {
-            let len = file.stream_position()?;
-
-            file.set_len(len)?;
-        }
+            .write_title_metadata_safe(&mut wad_stream, title_metadata)?;
 
+        self.trim_file_if_needed()?;
         self.sync_wad_header_content_size(title_metadata)?;
 
         Ok(())
@@ -289,16 +340,34 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
             .cryptographic_method
             .expect("Missing cryptographic method, use `.set_cryptography()` on the builder");
 
+        let key_provider = self
+            .key_provider
+            .expect("Missing key provider, use `.set_cryptography()` on the builder");
+
+        self.wad_stream.rewind()?;
         let mut wad_stream = StreamPin::new(&mut self.wad_stream)?;
         let physical_position = content_selector.physical_position(title_metadata)?;
 
-        let contents =
-            self.wad
-                .store_contents(&mut wad_stream, title_metadata, physical_position + 1)?;
-
         let mut new_data_vec = vec![];
         new_data.read_to_end(&mut new_data_vec)?;
 
+        let content_offset = self.wad.content_offset(title_metadata, content_selector)?;
+        let old_content_end_offset = content_offset
+            + util::align_to_boundary(
+                content_selector.content_entry(title_metadata)?.size,
+                InstallableWad::SECTION_BOUNDARY,
+            );
+        let new_content_end_offset = content_offset
+            + util::align_to_boundary(new_data_vec.len() as u64, InstallableWad::SECTION_BOUNDARY);
+
+        // Make room (or remove the spare one) for the new data
+        self.wad.shift_trailing_data(
+            &mut wad_stream,
+            title_metadata,
+            old_content_end_offset,
+            new_content_end_offset,
+        )?;
+
         let title_metadata_entry = &mut title_metadata.content_chunk_entries[physical_position];
 
         let hash = if title_metadata.version_1_extension.is_some() {
@@ -322,28 +391,33 @@ impl<'c, T: Read + Write + Seek + Any> ModifyContentBuilder<'_, '_, 'c, T> {
             title_metadata_entry.kind = kind;
         }
 
-        self.wad
-            .write_title_metadata_safe(&mut wad_stream, title_metadata)?;
+        // AES-CBC only works with full blocks
+        new_data_vec.resize(
+            util::align_to_boundary(new_data_vec.len() as u64, 16) as usize,
+            0,
+        );
 
-        self.wad
-            .seek_content(&mut wad_stream, title_metadata, content_selector)?;
+        wad_stream.seek(SeekFrom::Start(content_offset))?;
 
         let mut wad_stream = ticket.cryptographic_stream(
             &mut wad_stream,
             title_metadata,
             content_selector,
             cryptographic_method,
+            key_provider,
         )?;
 
         wad_stream.write(&new_data_vec)?;
 
         let wad_stream = wad_stream.into_inner();
+        wad_stream.align_zeroed(InstallableWad::SECTION_BOUNDARY)?;
 
-        wad_stream.align_position(InstallableWad::SECTION_BOUNDARY)?;
+        title_metadata.rehash();
 
         self.wad
-            .restore_contents(wad_stream, title_metadata, &contents)?;
+            .write_title_metadata_safe(&mut *wad_stream, title_metadata)?;
 
+        self.trim_file_if_needed()?;
         self.sync_wad_header_content_size(title_metadata)?;
 
         Ok(())
@@ -0,0 +1,317 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::certificate_chain::CertificateChain;
+use crate::title_metadata::TitleMetadataContentEntryKind;
+use crate::wad::installable::{
+    InstallableWad, InstallableWadError, InstallableWadKind, WadBuilder, WadBuilderContent,
+};
+use crate::{CryptographicMethod, KeyProvider, PreSwitchTicket, TitleMetadata};
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek, Write};
+use std::path::Path;
+use util::View;
+
+/// How the contents of a WAD will be stored when extracted into a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WadDirectoryContentFormat {
+    /// Only the decrypted data (`<ID>.app` files).
+    Decrypted,
+
+    /// Only the encrypted data (`<ID>.app.enc` files).
+    Encrypted,
+
+    /// Both the decrypted and the encrypted data.
+    Both,
+}
+
+struct WadDirectoryManifestContent {
+    id: u32,
+    index: u16,
+    kind: TitleMetadataContentEntryKind,
+    size: u64,
+}
+
+impl InstallableWad {
+    const DIRECTORY_CERTIFICATE_CHAIN_FILE_NAME: &str = "certificate_chain.cert";
+    const DIRECTORY_TICKET_FILE_NAME: &str = "ticket.tik";
+    const DIRECTORY_TITLE_METADATA_FILE_NAME: &str = "title_metadata.tmd";
+    const DIRECTORY_FOOTER_FILE_NAME: &str = "footer.bin";
+    const DIRECTORY_MANIFEST_FILE_NAME: &str = "manifest.txt";
+
+    fn directory_decrypted_content_file_name(id: u32) -> String {
+        format!("{id:08x}.app")
+    }
+
+    fn directory_encrypted_content_file_name(id: u32) -> String {
+        format!("{id:08x}.app.enc")
+    }
+
+    /// Extract all the data of the WAD into a directory (that will be created if needed): the
+    /// certificate chain, the ticket, the title metadata, the footer (if any) and the contents
+    /// (named by its ID), plus a manifest describing the order, IDs, indices and kinds of the
+    /// contents.
+    ///
+    /// The directory can be packed again into a WAD with [Self::build_from_dir].
+    pub fn extract_to_dir<T: Read + Seek, P: AsRef<Path>>(
+        &self,
+        mut stream: T,
+        path: P,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+        content_format: WadDirectoryContentFormat,
+    ) -> Result<(), InstallableWadError> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+
+        let mut certificate_chain_view = self.take_certificate_chain(&mut stream)?;
+        let mut file = File::create(path.join(Self::DIRECTORY_CERTIFICATE_CHAIN_FILE_NAME))?;
+        io::copy(&mut certificate_chain_view, &mut file)?;
+
+        let mut ticket_view = self.ticket_view(&mut stream)?;
+        let mut file = File::create(path.join(Self::DIRECTORY_TICKET_FILE_NAME))?;
+        io::copy(&mut ticket_view, &mut file)?;
+
+        let mut title_metadata_view = self.title_metadata_view(&mut stream)?;
+        let mut file = File::create(path.join(Self::DIRECTORY_TITLE_METADATA_FILE_NAME))?;
+        io::copy(&mut title_metadata_view, &mut file)?;
+
+        let ticket = self.ticket(&mut stream)?;
+        let title_metadata = self.title_metadata(&mut stream)?;
+
+        if let Some(mut footer_view) = self.footer_view(&mut stream, &title_metadata)? {
+            let mut file = File::create(path.join(Self::DIRECTORY_FOOTER_FILE_NAME))?;
+            io::copy(&mut footer_view, &mut file)?;
+        }
+
+        let mut manifest = String::from(
+            "# Manifest of a WAD directory, each content will be stored following the order of\n\
+             # this file. Lines starting with `#` are ignored.\n\
+             #\n\
+             # kind <Is|ib>\n\
+             # content <ID (hex)> <index> <kind (hex)> <size>\n",
+        );
+
+        let _ = writeln!(
+            manifest,
+            "kind {}",
+            match self.kind {
+                InstallableWadKind::Normal => "Is",
+                InstallableWadKind::Boot2 => "ib",
+            }
+        );
+
+        for (i, entry) in title_metadata.content_chunk_entries.iter().enumerate() {
+            let selector = title_metadata.select_with_physical_position(i);
+
+            let _ = writeln!(
+                manifest,
+                "content {:08x} {} {:04x} {}",
+                entry.id,
+                entry.index,
+                entry.kind.bits(),
+                entry.size
+            );
+
+            if content_format != WadDirectoryContentFormat::Encrypted {
+                let mut file =
+                    File::create(path.join(Self::directory_decrypted_content_file_name(entry.id)))?;
+
+                // A view cannot be empty
+                if entry.size > 0 {
+                    let mut view = self.decrypted_content_view(
+                        &mut stream,
+                        &ticket,
+                        &title_metadata,
+                        cryptographic_method,
+                        key_provider,
+                        selector,
+                    )?;
+
+                    io::copy(&mut view, &mut file)?;
+                }
+            }
+
+            if content_format != WadDirectoryContentFormat::Decrypted {
+                let mut file =
+                    File::create(path.join(Self::directory_encrypted_content_file_name(entry.id)))?;
+
+                // Keep the padding to the size of an AES block, it's needed to decrypt the last
+                // block
+                let encrypted_size = util::align_to_boundary(entry.size, 16);
+
+                if encrypted_size > 0 {
+                    self.seek_content(&mut stream, &title_metadata, selector)?;
+                    let mut view = View::new(&mut stream, encrypted_size as usize)?;
+
+                    io::copy(&mut view, &mut file)?;
+                }
+            }
+        }
+
+        fs::write(path.join(Self::DIRECTORY_MANIFEST_FILE_NAME), manifest)?;
+
+        Ok(())
+    }
+
+    /// Build a new WAD into a stream from a directory made by [Self::extract_to_dir]. The hashes
+    /// and sizes of the contents of the title metadata will be computed again.
+    ///
+    /// The decrypted data of a content (`<ID>.app`) will be used if available, if not then its
+    /// encrypted data (`<ID>.app.enc`) will be used.
+    pub fn build_from_dir<T: Write + Seek, P: AsRef<Path>>(
+        path: P,
+        stream: T,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<Self, InstallableWadError> {
+        let path = path.as_ref();
+
+        let certificate_chain = CertificateChain::new(
+            BufReader::new(File::open(
+                path.join(Self::DIRECTORY_CERTIFICATE_CHAIN_FILE_NAME),
+            )?),
+            Self::NUMBER_OF_CERTIFICATES_STORED,
+        )?;
+
+        let ticket = PreSwitchTicket::new(BufReader::new(File::open(
+            path.join(Self::DIRECTORY_TICKET_FILE_NAME),
+        )?))?;
+
+        let mut title_metadata = TitleMetadata::new(BufReader::new(File::open(
+            path.join(Self::DIRECTORY_TITLE_METADATA_FILE_NAME),
+        )?))?;
+
+        let footer = match fs::read(path.join(Self::DIRECTORY_FOOTER_FILE_NAME)) {
+            Ok(footer) => footer,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+
+        let manifest = fs::read_to_string(path.join(Self::DIRECTORY_MANIFEST_FILE_NAME))?;
+
+        let mut kind = None;
+        let mut contents = vec![];
+
+        for (i, line) in manifest.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid_line = || InstallableWadError::InvalidDirectoryManifest(i + 1);
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+
+            match fields.as_slice() {
+                ["kind", "Is"] => kind = Some(InstallableWadKind::Normal),
+                ["kind", "ib"] => kind = Some(InstallableWadKind::Boot2),
+
+                ["content", id, index, kind, size] => {
+                    contents.push(WadDirectoryManifestContent {
+                        id: u32::from_str_radix(id, 16).map_err(|_| invalid_line())?,
+                        index: index.parse().map_err(|_| invalid_line())?,
+                        kind: TitleMetadataContentEntryKind::from_bits_retain(
+                            u16::from_str_radix(kind, 16).map_err(|_| invalid_line())?,
+                        ),
+                        size: size.parse().map_err(|_| invalid_line())?,
+                    });
+                }
+
+                _ => return Err(invalid_line()),
+            }
+        }
+
+        let mut builder = WadBuilder::new(
+            &certificate_chain,
+            &ticket,
+            &mut title_metadata,
+            cryptographic_method,
+            key_provider,
+        );
+
+        if let Some(kind) = kind {
+            builder.set_kind(kind);
+        }
+
+        if !footer.is_empty() {
+            builder.set_footer(&footer);
+        }
+
+        for content in contents {
+            let decrypted_path = path.join(Self::directory_decrypted_content_file_name(content.id));
+            let encrypted_path = path.join(Self::directory_encrypted_content_file_name(content.id));
+
+            let data = if decrypted_path.exists() {
+                WadBuilderContent::Decrypted(Box::new(BufReader::new(File::open(decrypted_path)?)))
+            } else if encrypted_path.exists() {
+                WadBuilderContent::Encrypted {
+                    data: Box::new(BufReader::new(File::open(encrypted_path)?)),
+                    size: content.size,
+                }
+            } else {
+                return Err(InstallableWadError::MissingDirectoryContent(content.id));
+            };
+
+            builder.add_content(content.id, content.index, content.kind, data);
+        }
+
+        builder.build(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedKeyProvider;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+    use std::io::Cursor;
+
+    fn directory_round_trip(content_format: WadDirectoryContentFormat) {
+        let bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+        let path = std::env::temp_dir().join(format!(
+            "zelzip_niiebla_wad_directory_{content_format:?}_{}",
+            std::process::id()
+        ));
+
+        let wad = Wad::try_new_installable(Cursor::new(&bytes)).unwrap();
+        wad.extract_to_dir(
+            Cursor::new(&bytes),
+            &path,
+            CryptographicMethod::Wii,
+            &EmbeddedKeyProvider,
+            content_format,
+        )
+        .unwrap();
+
+        let mut rebuilt = Cursor::new(vec![]);
+        let result = InstallableWad::build_from_dir(
+            &path,
+            &mut rebuilt,
+            CryptographicMethod::Wii,
+            &EmbeddedKeyProvider,
+        );
+
+        fs::remove_dir_all(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(rebuilt.into_inner(), bytes);
+    }
+
+    #[test]
+    fn directory_round_trip_decrypted() {
+        directory_round_trip(WadDirectoryContentFormat::Decrypted);
+    }
+
+    #[test]
+    fn directory_round_trip_encrypted() {
+        directory_round_trip(WadDirectoryContentFormat::Encrypted);
+    }
+}
@@ -0,0 +1,174 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::TitleMetadata;
+use crate::wad::InstallableWad;
+use crate::wad::InstallableWadError;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use util::{StreamPin, View};
+
+impl InstallableWad {
+    pub(super) fn footer_offset(&self, title_metadata: &TitleMetadata) -> u64 {
+        self.content_section_offset()
+            + title_metadata
+                .content_chunk_entries
+                .iter()
+                .fold(0, |acc, entry| {
+                    acc + util::align_to_boundary(entry.size, Self::SECTION_BOUNDARY)
+                })
+    }
+
+    pub(super) fn end_offset(&self, title_metadata: &TitleMetadata) -> u64 {
+        self.footer_offset(title_metadata) + Self::align_u64(self.footer_size)
+    }
+
+    /// Seek the stream of the WAD to the start of the footer.
+    pub fn seek_footer<T: Seek>(
+        &self,
+        mut stream: T,
+        title_metadata: &TitleMetadata,
+    ) -> Result<(), InstallableWadError> {
+        stream.seek(SeekFrom::Start(self.footer_offset(title_metadata)))?;
+        Ok(())
+    }
+
+    /// Create a [View] into the footer stored inside the WAD stream, `None` if the WAD has no
+    /// footer.
+    pub fn footer_view<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        title_metadata: &TitleMetadata,
+    ) -> Result<Option<View<T>>, InstallableWadError> {
+        if self.footer_size == 0 {
+            return Ok(None);
+        }
+
+        self.seek_footer(&mut stream, title_metadata)?;
+
+        Ok(Some(View::new(stream, self.footer_size as usize)?))
+    }
+
+    /// Get the bytes of the footer stored inside the WAD stream. Its format is not fixed, usually
+    /// it is build metadata or an `IMET` header of the banner of the title.
+    pub fn footer<T: Read + Seek>(
+        &self,
+        stream: T,
+        title_metadata: &TitleMetadata,
+    ) -> Result<Vec<u8>, InstallableWadError> {
+        let mut footer = vec![];
+
+        if let Some(mut view) = self.footer_view(stream, title_metadata)? {
+            view.read_to_end(&mut footer)?;
+        }
+
+        Ok(footer)
+    }
+
+    /// Write a new footer into the stream of a WAD, an empty footer will strip it from the WAD.
+    /// The internal WAD data will be modified to match the new size of the footer.
+    ///
+    /// Given that the footer is the last section of the WAD no data is needed to be realigned.
+    pub fn write_footer_safe<T: Write + Seek>(
+        &mut self,
+        mut stream: T,
+        new_footer: &[u8],
+        title_metadata: &TitleMetadata,
+    ) -> Result<(), InstallableWadError> {
+        stream.rewind()?;
+        let mut stream = StreamPin::new(stream)?;
+
+        self.seek_footer(&mut stream, title_metadata)?;
+
+        stream.write_all(new_footer)?;
+        stream.align_zeroed(Self::SECTION_BOUNDARY)?;
+
+        let end_position = stream.stream_position()?;
+
+        self.footer_size = new_footer.len() as u32;
+
+        stream.rewind()?;
+        self.dump(&mut stream)?;
+
+        // Leave the stream at the end of the WAD, useful to trim it
+        stream.seek(SeekFrom::Start(end_position))?;
+
+        Ok(())
+    }
+
+    /// Like [Self::write_footer_safe] but will also trim the size of the file to avoid garbage
+    /// data or useless zeroes.
+    pub fn write_footer_safe_file(
+        &mut self,
+        file: &mut File,
+        new_footer: &[u8],
+        title_metadata: &TitleMetadata,
+    ) -> Result<(), InstallableWadError> {
+        self.write_footer_safe(&mut *file, new_footer, title_metadata)?;
+
+        let new_file_size = file.stream_position()?;
+        file.set_len(new_file_size)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+    use std::io::Cursor;
+
+    #[test]
+    fn replace_footer() {
+        let mut stream = Cursor::new(test_fixtures::installable_wad_bytes(&[0xEF; 0x21]));
+
+        let mut wad = Wad::try_new_installable(&mut stream).unwrap();
+        let title_metadata = wad.title_metadata(&mut stream).unwrap();
+        let footer_offset = wad.footer_offset(&title_metadata);
+
+        assert_eq!(
+            wad.footer(&mut stream, &title_metadata).unwrap(),
+            [0xEF; 0x21]
+        );
+
+        for new_footer in [&[0x12; 0x90][..], &[0x34; 0x10][..], &[]] {
+            wad.write_footer_safe(&mut stream, new_footer, &title_metadata)
+                .unwrap();
+
+            // Trim the stream, as done with files
+            let end_position = stream.position();
+            stream.get_mut().truncate(end_position as usize);
+
+            assert_eq!(
+                end_position,
+                footer_offset + util::align_to_boundary(new_footer.len() as u64, 64)
+            );
+            assert!(
+                stream.get_ref()[(footer_offset as usize + new_footer.len())..]
+                    .iter()
+                    .all(|byte| *byte == 0)
+            );
+
+            stream.rewind().unwrap();
+            let parsed_wad = Wad::try_new_installable(&mut stream).unwrap();
+
+            assert_eq!(parsed_wad.footer_size, new_footer.len() as u32);
+            assert_eq!(
+                parsed_wad.footer(&mut stream, &title_metadata).unwrap(),
+                new_footer
+            );
+            assert!(
+                parsed_wad
+                    .footer_view(&mut stream, &title_metadata)
+                    .unwrap()
+                    .is_none()
+                    == new_footer.is_empty()
+            );
+        }
+    }
+}
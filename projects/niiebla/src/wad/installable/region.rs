@@ -0,0 +1,121 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::KeyProvider;
+use crate::title_metadata::{TitleMetadataPlatformData, TitleMetadataPlatformDataWiiRegion};
+use crate::wad::installable::{InstallableWad, InstallableWadError};
+use crate::wii_common_key::WiiCommonKeyKind;
+use std::io::{Read, Seek, Write};
+
+impl InstallableWad {
+    /// Change the region of the title stored inside the WAD, optionally changing also the common
+    /// key used to encrypt its title key (like converting a Korean title into a normal one, see
+    /// [crate::PreSwitchTicket::change_common_key]). The ticket and the title metadata will be
+    /// written again into the stream.
+    ///
+    /// As the signatures will be no longer valid both the ticket and the title metadata can be
+    /// fakesigned, if not they will need to be signed again afterwards.
+    pub fn change_region<T: Read + Write + Seek>(
+        &mut self,
+        mut stream: T,
+        new_region: TitleMetadataPlatformDataWiiRegion,
+        new_common_key: Option<WiiCommonKeyKind>,
+        key_provider: &dyn KeyProvider,
+        fakesign: bool,
+    ) -> Result<(), InstallableWadError> {
+        let mut ticket = self.ticket(&mut stream)?;
+        let mut title_metadata = self.title_metadata(&mut stream)?;
+
+        let TitleMetadataPlatformData::Wii { region, .. } = &mut title_metadata.platform_data
+        else {
+            return Err(InstallableWadError::NotAWiiTitle);
+        };
+
+        *region = new_region;
+
+        if let Some(new_common_key) = new_common_key {
+            ticket.change_common_key(new_common_key, key_provider)?;
+        }
+
+        if fakesign {
+            ticket.fakesign()?;
+            title_metadata.fakesign()?;
+        }
+
+        self.write_ticket_safe(&mut stream, &ticket, &title_metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signed_blob_header::SignedBlobHeaderVerification;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+    use crate::{CryptographicMethod, EmbeddedKeyProvider, TitleMetadata};
+    use std::io::{Cursor, SeekFrom};
+
+    fn section_offsets(wad: &InstallableWad, title_metadata: &TitleMetadata) -> [u64; 3] {
+        [
+            wad.content_offset(title_metadata, title_metadata.select_with_index(0))
+                .unwrap(),
+            wad.content_offset(title_metadata, title_metadata.select_with_index(1))
+                .unwrap(),
+            wad.footer_offset(title_metadata),
+        ]
+    }
+
+    #[test]
+    fn change_region() {
+        let mut stream = Cursor::new(test_fixtures::installable_wad_bytes(&[0xEF; 0x21]));
+        let mut wad = Wad::try_new_installable(&mut stream).unwrap();
+        let offsets = section_offsets(&wad, &wad.title_metadata(&mut stream).unwrap());
+        let len = stream.get_ref().len();
+
+        wad.change_region(
+            &mut stream,
+            TitleMetadataPlatformDataWiiRegion::Korea,
+            Some(WiiCommonKeyKind::Korean),
+            &EmbeddedKeyProvider,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(stream.get_ref().len(), len);
+
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        let wad = Wad::try_new_installable(&mut stream).unwrap();
+        let certificate_chain = wad.certificate_chain(&mut stream).unwrap();
+        let ticket = wad.ticket(&mut stream).unwrap();
+        let title_metadata = wad.title_metadata(&mut stream).unwrap();
+
+        assert!(matches!(
+            title_metadata.platform_data,
+            TitleMetadataPlatformData::Wii {
+                region: TitleMetadataPlatformDataWiiRegion::Korea,
+                ..
+            }
+        ));
+        assert_eq!(ticket.common_key_kind_index, 1);
+        assert_eq!(section_offsets(&wad, &title_metadata), offsets);
+
+        assert_eq!(
+            ticket.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Fakesigned
+        );
+        assert_eq!(
+            title_metadata.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Fakesigned
+        );
+
+        // The contents can still be decrypted with the new common key
+        let report = wad
+            .verify(&mut stream, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert_eq!(report.issues, []);
+    }
+}
@@ -53,8 +53,9 @@ impl InstallableWad {
     pub unsafe fn write_ticket_raw<T: Write + Seek>(
         &mut self,
         new_ticket: &PreSwitchTicket,
-        stream: T,
+        mut stream: T,
     ) -> Result<(), PreSwitchTicketError> {
+        stream.rewind()?;
         let mut stream = StreamPin::new(stream)?;
 
         self.seek_ticket(&mut stream)?;
@@ -70,8 +71,8 @@ impl InstallableWad {
         Ok(())
     }
 
-    /// Like [Self::write_ticket_raw] but will make a in-memory copy off all the trailing data to
-    /// realign it.
+    /// Like [Self::write_ticket_raw] but will move all the trailing data in place to realign it,
+    /// memory usage does not depend on the size of the WAD.
     pub fn write_ticket_safe<T: Read + Write + Seek>(
         &mut self,
         stream: T,
@@ -80,14 +81,25 @@ impl InstallableWad {
     ) -> Result<(), InstallableWadError> {
         let mut stream = StreamPin::new(stream)?;
 
-        let contents = self.store_contents(&mut stream, title_metadata, 0)?;
+        let new_content_section_offset = Self::HEADER_SIZE
+            + Self::align_u64(self.certificate_chain_size)
+            + Self::align_u64(new_ticket.size())
+            + Self::align_u64(title_metadata.size());
+
+        self.shift_trailing_data(
+            &mut stream,
+            title_metadata,
+            self.content_section_offset(),
+            new_content_section_offset,
+        )?;
 
         unsafe {
             self.write_ticket_raw(new_ticket, &mut stream)?;
             self.write_title_metadata_raw(title_metadata, &mut stream)?;
         }
 
-        self.restore_contents(&mut stream, title_metadata, &contents)?;
+        // Leave the stream at the end of the WAD, useful to trim it
+        stream.seek(SeekFrom::Start(self.end_offset(title_metadata)))?;
 
         Ok(())
     }
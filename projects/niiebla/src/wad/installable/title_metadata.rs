@@ -4,7 +4,8 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::title_metadata::{TitleMetadata, TitleMetadataError};
+use crate::title_id::TitleId;
+use crate::title_metadata::{TitleMetadata, TitleMetadataError, TitleMetadataSystemRuntimeWarning};
 use crate::wad::InstallableWad;
 use crate::wad::InstallableWadError;
 use std::fs::File;
@@ -52,8 +53,9 @@ impl InstallableWad {
     pub unsafe fn write_title_metadata_raw<T: Write + Seek>(
         &mut self,
         new_title_metadata: &TitleMetadata,
-        stream: T,
+        mut stream: T,
     ) -> Result<(), TitleMetadataError> {
+        stream.rewind()?;
         let mut stream = StreamPin::new(stream)?;
 
         self.seek_title_metadata(&mut stream)?;
@@ -69,8 +71,8 @@ impl InstallableWad {
         Ok(())
     }
 
-    /// Like [Self::write_title_metadata_raw] but will make a in-memory copy off all the trailing data to
-    /// realign it.
+    /// Like [Self::write_title_metadata_raw] but will move all the trailing data in place to realign it,
+    /// memory usage does not depend on the size of the WAD.
     ///
     /// Be aware that the given new title metadata should have cohesion with the stored content
     /// blobs.
@@ -81,13 +83,24 @@ impl InstallableWad {
     ) -> Result<(), InstallableWadError> {
         let mut stream = StreamPin::new(stream)?;
 
-        let contents = self.store_contents(&mut stream, new_title_metadata, 0)?;
+        let new_content_section_offset = Self::HEADER_SIZE
+            + Self::align_u64(self.certificate_chain_size)
+            + Self::align_u64(self.ticket_size)
+            + Self::align_u64(new_title_metadata.size());
+
+        self.shift_trailing_data(
+            &mut stream,
+            new_title_metadata,
+            self.content_section_offset(),
+            new_content_section_offset,
+        )?;
 
         unsafe {
             self.write_title_metadata_raw(new_title_metadata, &mut stream)?;
         }
 
-        self.restore_contents(&mut stream, new_title_metadata, &contents)?;
+        // Leave the stream at the end of the WAD, useful to trim it
+        stream.seek(SeekFrom::Start(self.end_offset(new_title_metadata)))?;
 
         Ok(())
     }
@@ -109,4 +122,90 @@ impl InstallableWad {
 
         Ok(())
     }
+
+    /// Change the IOS the title stored inside the WAD runs on (see
+    /// [TitleMetadata::set_system_runtime_ios_wii]), the title metadata will be written again
+    /// into the stream and optionally fakesigned (if not it will need to be signed again
+    /// afterwards).
+    pub fn change_system_runtime_ios<T: Read + Write + Seek>(
+        &mut self,
+        mut stream: T,
+        ios: TitleId,
+        fakesign: bool,
+    ) -> Result<Option<TitleMetadataSystemRuntimeWarning>, InstallableWadError> {
+        let mut title_metadata = self.title_metadata(&mut stream)?;
+        let warning = title_metadata.set_system_runtime_ios_wii(ios)?;
+
+        if fakesign {
+            title_metadata.fakesign()?;
+        }
+
+        self.write_title_metadata_safe(&mut stream, &title_metadata)?;
+
+        Ok(warning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::signed_blob_header::SignedBlobHeaderVerification;
+    use crate::test_fixtures;
+    use crate::title_id::TitleId;
+    use crate::title_metadata::TitleMetadataError;
+    use crate::wad::{InstallableWadError, Wad};
+    use crate::{CryptographicMethod, EmbeddedKeyProvider};
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    #[test]
+    fn change_system_runtime_ios() {
+        let bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+        let mut stream = Cursor::new(bytes.clone());
+        let mut wad = Wad::try_new_installable(&mut stream).unwrap();
+
+        const IOS_80: u64 = 0x0000_0001_0000_0050;
+        let warning = wad
+            .change_system_runtime_ios(&mut stream, TitleId::new(IOS_80), true)
+            .unwrap();
+
+        assert_eq!(warning, None);
+        assert_eq!(stream.get_ref().len(), bytes.len());
+
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        let wad = Wad::try_new_installable(&mut stream).unwrap();
+        let certificate_chain = wad.certificate_chain(&mut stream).unwrap();
+        let title_metadata = wad.title_metadata(&mut stream).unwrap();
+
+        assert_eq!(
+            title_metadata
+                .system_runtime_title_id
+                .as_ref()
+                .map(TitleId::inner),
+            Some(IOS_80)
+        );
+        assert_eq!(
+            title_metadata.verify_signature(&certificate_chain).unwrap(),
+            SignedBlobHeaderVerification::Fakesigned
+        );
+
+        let report = wad
+            .verify(&mut stream, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap();
+
+        assert_eq!(report.issues, []);
+    }
+
+    #[test]
+    fn change_system_runtime_ios_to_not_an_ios() {
+        let bytes = test_fixtures::installable_wad_bytes(&[]);
+        let mut stream = Cursor::new(bytes.clone());
+        let mut wad = Wad::try_new_installable(&mut stream).unwrap();
+
+        assert!(matches!(
+            wad.change_system_runtime_ios(&mut stream, TitleId::new(test_fixtures::TITLE_ID), true),
+            Err(InstallableWadError::TitleMetadataError(
+                TitleMetadataError::NotAnIosTitleId(test_fixtures::TITLE_ID)
+            ))
+        ));
+        assert_eq!(stream.into_inner(), bytes);
+    }
 }
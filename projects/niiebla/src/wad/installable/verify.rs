@@ -0,0 +1,624 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::CryptographicMethod;
+use crate::KeyProvider;
+use crate::title_metadata::{
+    TitleMetadataContentEntryHashKind, TitleMetadataPlatformData, TitleMetadataV1Mismatch,
+};
+use crate::wad::installable::{InstallableWad, InstallableWadError};
+use crate::{ContentSelector, PreSwitchTicket, TitleMetadata};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Report of the integrity of an installable WAD, made by [InstallableWad::verify].
+#[derive(Debug, Default)]
+pub struct InstallableWadVerifyReport {
+    /// All the issues found on the WAD, empty if the WAD is intact.
+    pub issues: Vec<InstallableWadVerifyIssue>,
+}
+
+impl InstallableWadVerifyReport {
+    /// Either if no issue was found on the WAD or not.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// An issue found while verifying the integrity of an installable WAD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallableWadVerifyIssue {
+    /// The size of the header of the WAD is not the expected one.
+    UnexpectedHeaderSize(u32),
+
+    /// The size of a section stored on the header of the WAD does not match the size of its
+    /// parsed data.
+    SectionSizeMismatch {
+        /// The section with the mismatched size.
+        section: InstallableWadSection,
+
+        /// The size stored on the header of the WAD.
+        header_size: u32,
+
+        /// The size of the parsed data.
+        parsed_size: u32,
+    },
+
+    /// The size of the content blobs stored on the header of the WAD does not match the sum of
+    /// the sizes of the title metadata entries.
+    ContentSizeMismatch {
+        /// The size stored on the header of the WAD.
+        header_size: u32,
+
+        /// The sum of the sizes of the title metadata entries.
+        title_metadata_size: u64,
+    },
+
+    /// The stream of the WAD ends before the end of a content.
+    TruncatedContent {
+        /// The ID of the content.
+        id: u32,
+
+        /// The index of the content.
+        index: u16,
+
+        /// The size of the encrypted data of the content.
+        size: u64,
+
+        /// The number of bytes available on the stream for the content.
+        available_size: u64,
+    },
+
+    /// The stream of the WAD ends before the end of the footer.
+    TruncatedFooter {
+        /// The size of the footer.
+        size: u32,
+
+        /// The number of bytes available on the stream for the footer.
+        available_size: u64,
+    },
+
+    /// The padding after a section up to the 64 bytes boundary is not zeroed, usually because
+    /// the sections of the WAD are not aligned to the boundary.
+    UnalignedSection {
+        /// The section followed by the padding.
+        section: InstallableWadSection,
+
+        /// The offset of the first byte of the padding that is not zero.
+        offset: u64,
+    },
+
+    /// The stream of the WAD has data after the end of the footer (and its padding to the 64
+    /// bytes boundary of the sections), holds the number of trailing bytes.
+    TrailingData(u64),
+
+    /// The data of a content cannot be decrypted or read, usually because its key is not
+    /// available.
+    ContentUnreadable {
+        /// The ID of the content.
+        id: u32,
+
+        /// The index of the content.
+        index: u16,
+
+        /// The description of the error found while reading the content.
+        error: String,
+    },
+
+    /// The hash of the decrypted data of a content does not match the one stored on its title
+    /// metadata entry.
+    ContentHashMismatch {
+        /// The ID of the content.
+        id: u32,
+
+        /// The index of the content.
+        index: u16,
+    },
+
+    /// The content entries groups of the V1 extension of the title metadata (or their hashes) do
+    /// not match its content entries.
+    TitleMetadataV1Mismatch(TitleMetadataV1Mismatch),
+
+    /// The title ID of the ticket and the title metadata are not the same.
+    TitleIdMismatch {
+        /// The title ID of the ticket.
+        ticket: u64,
+
+        /// The title ID of the title metadata.
+        title_metadata: u64,
+    },
+
+    /// The title version of the ticket and the title metadata are not the same.
+    TitleVersionMismatch {
+        /// The title version of the ticket.
+        ticket: u16,
+
+        /// The title version of the title metadata.
+        title_metadata: u16,
+    },
+}
+
+/// The sections of an installable WAD, each one aligned to a 64 bytes boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallableWadSection {
+    /// The certificate chain.
+    CertificateChain,
+
+    /// The ticket.
+    Ticket,
+
+    /// The title metadata.
+    TitleMetadata,
+
+    /// The encrypted data of a content.
+    Content {
+        /// The ID of the content.
+        id: u32,
+
+        /// The index of the content.
+        index: u16,
+    },
+
+    /// The footer.
+    Footer,
+}
+
+impl InstallableWad {
+    const EXPECTED_HEADER_SIZE: u32 = 32;
+    const VERIFY_BUFFER_SIZE: usize = 64 * 1024;
+
+    /// Check the integrity of the WAD, all the issues found are stored on the returned report
+    /// instead of failing on the first one. The decrypted data of each content is hashed and
+    /// compared with its entry in the title metadata, and the padding of each section up to the
+    /// 64 bytes boundary is checked to be zeroed.
+    ///
+    /// An error is only returned if the certificate chain, the ticket or the title metadata
+    /// cannot be parsed, contents that cannot be decrypted or read are reported as
+    /// [InstallableWadVerifyIssue::ContentUnreadable].
+    pub fn verify<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<InstallableWadVerifyReport, InstallableWadError> {
+        let mut report = InstallableWadVerifyReport::default();
+
+        let certificate_chain = self.certificate_chain(&mut stream)?;
+        let ticket = self.ticket(&mut stream)?;
+        let title_metadata = self.title_metadata(&mut stream)?;
+
+        let stream_len = stream.seek(SeekFrom::End(0))?;
+
+        if self.header_size != Self::EXPECTED_HEADER_SIZE {
+            report
+                .issues
+                .push(InstallableWadVerifyIssue::UnexpectedHeaderSize(
+                    self.header_size,
+                ));
+        }
+
+        for (section, header_size, parsed_size) in [
+            (
+                InstallableWadSection::CertificateChain,
+                self.certificate_chain_size,
+                certificate_chain.size(),
+            ),
+            (
+                InstallableWadSection::Ticket,
+                self.ticket_size,
+                ticket.size(),
+            ),
+            (
+                InstallableWadSection::TitleMetadata,
+                self.title_metadata_size,
+                title_metadata.size(),
+            ),
+        ] {
+            if header_size != parsed_size {
+                report
+                    .issues
+                    .push(InstallableWadVerifyIssue::SectionSizeMismatch {
+                        section,
+                        header_size,
+                        parsed_size,
+                    });
+            }
+        }
+
+        let mut section_offset = Self::HEADER_SIZE;
+
+        for (section, size) in [
+            (
+                InstallableWadSection::CertificateChain,
+                self.certificate_chain_size,
+            ),
+            (InstallableWadSection::Ticket, self.ticket_size),
+            (
+                InstallableWadSection::TitleMetadata,
+                self.title_metadata_size,
+            ),
+        ] {
+            self.verify_section_padding(
+                &mut stream,
+                &mut report,
+                section,
+                section_offset + size as u64,
+                stream_len,
+            )?;
+
+            section_offset += Self::align_u64(size);
+        }
+
+        let title_metadata_content_size = title_metadata
+            .content_chunk_entries
+            .iter()
+            .fold(0, |acc, entry| acc + entry.size);
+
+        if self.content_size as u64 != title_metadata_content_size {
+            report
+                .issues
+                .push(InstallableWadVerifyIssue::ContentSizeMismatch {
+                    header_size: self.content_size,
+                    title_metadata_size: title_metadata_content_size,
+                });
+        }
+
+        if ticket.title_id.inner() != title_metadata.title_id.inner() {
+            report
+                .issues
+                .push(InstallableWadVerifyIssue::TitleIdMismatch {
+                    ticket: ticket.title_id.inner(),
+                    title_metadata: title_metadata.title_id.inner(),
+                });
+        }
+
+        if ticket.title_version != title_metadata.title_version {
+            report
+                .issues
+                .push(InstallableWadVerifyIssue::TitleVersionMismatch {
+                    ticket: ticket.title_version,
+                    title_metadata: title_metadata.title_version,
+                });
+        }
+
+        report.issues.extend(
+            title_metadata
+                .verify_v1_hashes()
+                .into_iter()
+                .map(InstallableWadVerifyIssue::TitleMetadataV1Mismatch),
+        );
+
+        let is_wii_u = matches!(
+            title_metadata.platform_data,
+            TitleMetadataPlatformData::WiiU
+        );
+
+        for (i, entry) in title_metadata.content_chunk_entries.iter().enumerate() {
+            let selector = title_metadata.select_with_physical_position(i);
+
+            // The encrypted data is padded to the size of an AES block
+            let encrypted_size = util::align_to_boundary(entry.size, 16);
+            let content_offset = self.content_offset(&title_metadata, selector)?;
+            let available_size = stream_len
+                .saturating_sub(content_offset)
+                .min(encrypted_size);
+
+            if available_size < encrypted_size {
+                report
+                    .issues
+                    .push(InstallableWadVerifyIssue::TruncatedContent {
+                        id: entry.id,
+                        index: entry.index,
+                        size: encrypted_size,
+                        available_size,
+                    });
+
+                continue;
+            }
+
+            self.verify_section_padding(
+                &mut stream,
+                &mut report,
+                InstallableWadSection::Content {
+                    id: entry.id,
+                    index: entry.index,
+                },
+                content_offset + encrypted_size,
+                stream_len,
+            )?;
+
+            let (sha1, sha256) = match self.hash_decrypted_content(
+                &mut stream,
+                &ticket,
+                &title_metadata,
+                cryptographic_method,
+                key_provider,
+                selector,
+            ) {
+                Ok(hashes) => hashes,
+
+                Err(error) => {
+                    report
+                        .issues
+                        .push(InstallableWadVerifyIssue::ContentUnreadable {
+                            id: entry.id,
+                            index: entry.index,
+                            error: error.to_string(),
+                        });
+
+                    continue;
+                }
+            };
+
+            let is_hash_valid = match &entry.hash {
+                TitleMetadataContentEntryHashKind::Version0(hash) => {
+                    sha1.finalize().as_slice() == hash
+                }
+
+                // On Wii U titles the hash is a SHA-1 one padded with zeroes
+                TitleMetadataContentEntryHashKind::Version1(hash) if is_wii_u => {
+                    hash[..20] == *sha1.finalize().as_slice() && hash[20..] == [0; 12]
+                }
+
+                TitleMetadataContentEntryHashKind::Version1(hash) => {
+                    sha256.finalize().as_slice() == hash
+                }
+            };
+
+            if !is_hash_valid {
+                report
+                    .issues
+                    .push(InstallableWadVerifyIssue::ContentHashMismatch {
+                        id: entry.id,
+                        index: entry.index,
+                    });
+            }
+        }
+
+        if self.footer_size > 0 {
+            let available_size = stream_len
+                .saturating_sub(self.footer_offset(&title_metadata))
+                .min(self.footer_size as u64);
+
+            if available_size < self.footer_size as u64 {
+                report
+                    .issues
+                    .push(InstallableWadVerifyIssue::TruncatedFooter {
+                        size: self.footer_size,
+                        available_size,
+                    });
+            } else {
+                self.verify_section_padding(
+                    &mut stream,
+                    &mut report,
+                    InstallableWadSection::Footer,
+                    self.footer_offset(&title_metadata) + self.footer_size as u64,
+                    stream_len,
+                )?;
+            }
+        }
+
+        // The footer may not be padded to the boundary of the sections, only data past its
+        // padding is unexpected
+        let end_offset = self.end_offset(&title_metadata);
+
+        if stream_len > end_offset {
+            report.issues.push(InstallableWadVerifyIssue::TrailingData(
+                stream_len - end_offset,
+            ));
+        }
+
+        Ok(report)
+    }
+
+    // Check that the padding after the end of the data of a section up to the boundary of the
+    // sections is zeroed, the padding may be missing at the end of the stream
+    fn verify_section_padding<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        report: &mut InstallableWadVerifyReport,
+        section: InstallableWadSection,
+        data_end_offset: u64,
+        stream_len: u64,
+    ) -> Result<(), InstallableWadError> {
+        let padding_end_offset =
+            util::align_to_boundary(data_end_offset, Self::SECTION_BOUNDARY).min(stream_len);
+
+        if padding_end_offset <= data_end_offset {
+            return Ok(());
+        }
+
+        let mut padding = vec![0; (padding_end_offset - data_end_offset) as usize];
+        stream.seek(SeekFrom::Start(data_end_offset))?;
+        stream.read_exact(&mut padding)?;
+
+        if let Some(position) = padding.iter().position(|byte| *byte != 0) {
+            report
+                .issues
+                .push(InstallableWadVerifyIssue::UnalignedSection {
+                    section,
+                    offset: data_end_offset + position as u64,
+                });
+        }
+
+        Ok(())
+    }
+
+    fn hash_decrypted_content<T: Read + Seek>(
+        &self,
+        mut stream: T,
+        ticket: &PreSwitchTicket,
+        title_metadata: &TitleMetadata,
+        cryptographic_method: CryptographicMethod,
+        key_provider: &dyn KeyProvider,
+        selector: ContentSelector,
+    ) -> Result<(Sha1, Sha256), InstallableWadError> {
+        let mut sha1 = Sha1::new();
+        let mut sha256 = Sha256::new();
+
+        let entry = selector.content_entry(title_metadata)?;
+
+        // A view cannot be empty, the hash of empty data is used instead
+        if entry.size == 0 {
+            return Ok((sha1, sha256));
+        }
+
+        let mut decrypted_view = self.decrypted_content_view(
+            &mut stream,
+            ticket,
+            title_metadata,
+            cryptographic_method,
+            key_provider,
+            selector,
+        )?;
+
+        let mut buffer = vec![0; Self::VERIFY_BUFFER_SIZE];
+        let mut remaining = entry.size;
+
+        while remaining > 0 {
+            let chunk_len = remaining.min(buffer.len() as u64) as usize;
+            decrypted_view.read_exact(&mut buffer[..chunk_len])?;
+
+            sha1.update(&buffer[..chunk_len]);
+            sha256.update(&buffer[..chunk_len]);
+
+            remaining -= chunk_len as u64;
+        }
+
+        Ok((sha1, sha256))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedKeyProvider;
+    use crate::test_fixtures;
+    use crate::wad::Wad;
+    use std::io::Cursor;
+
+    fn header_size_field(bytes: &[u8], offset: usize) -> usize {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    /// Offset and size of the ticket.
+    fn ticket_location(bytes: &[u8]) -> (usize, usize) {
+        let certificate_chain_size = header_size_field(bytes, 0x08);
+        let offset = 0x40 + certificate_chain_size.next_multiple_of(0x40);
+
+        (offset, header_size_field(bytes, 0x10))
+    }
+
+    fn verify(bytes: &[u8]) -> Vec<InstallableWadVerifyIssue> {
+        let mut stream = Cursor::new(bytes);
+        let wad = Wad::try_new_installable(&mut stream).unwrap();
+
+        wad.verify(&mut stream, CryptographicMethod::Wii, &EmbeddedKeyProvider)
+            .unwrap()
+            .issues
+    }
+
+    fn content_offset(bytes: &[u8], index: u16) -> usize {
+        let mut stream = Cursor::new(bytes);
+        let wad = Wad::try_new_installable(&mut stream).unwrap();
+        let title_metadata = wad.title_metadata(&mut stream).unwrap();
+
+        wad.content_offset(&title_metadata, title_metadata.select_with_index(index))
+            .unwrap() as usize
+    }
+
+    #[test]
+    fn intact() {
+        let bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+
+        assert_eq!(verify(&bytes), []);
+
+        // Without the padding of the footer
+        assert_eq!(verify(&bytes[..bytes.len() - 0x1F]), []);
+    }
+
+    #[test]
+    fn content_hash_mismatch() {
+        let mut bytes = test_fixtures::installable_wad_bytes(&[]);
+        let offset = content_offset(&bytes, 0);
+        bytes[offset] ^= 1;
+
+        assert_eq!(
+            verify(&bytes),
+            [InstallableWadVerifyIssue::ContentHashMismatch { id: 0x10, index: 0 }]
+        );
+    }
+
+    #[test]
+    fn truncated_content() {
+        let bytes = test_fixtures::installable_wad_bytes(&[]);
+        let end = content_offset(&bytes, 1) + 0x20;
+
+        assert_eq!(
+            verify(&bytes[..end]),
+            [InstallableWadVerifyIssue::TruncatedContent {
+                id: 0x20,
+                index: 1,
+                size: 0x40,
+                available_size: 0x20,
+            }]
+        );
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+        bytes.extend_from_slice(&[0; 0x50]);
+
+        assert_eq!(
+            verify(&bytes),
+            [InstallableWadVerifyIssue::TrailingData(0x50)]
+        );
+    }
+
+    #[test]
+    fn section_size_mismatch() {
+        let mut bytes = test_fixtures::installable_wad_bytes(&[]);
+        let (_, ticket_size) = ticket_location(&bytes);
+
+        // The new size is still inside the same 64 bytes boundary
+        bytes[0x10..0x14].copy_from_slice(&(ticket_size as u32 + 4).to_be_bytes());
+
+        assert_eq!(
+            verify(&bytes),
+            [InstallableWadVerifyIssue::SectionSizeMismatch {
+                section: InstallableWadSection::Ticket,
+                header_size: ticket_size as u32 + 4,
+                parsed_size: ticket_size as u32,
+            }]
+        );
+    }
+
+    #[test]
+    fn unaligned_section() {
+        let mut bytes = test_fixtures::installable_wad_bytes(&[0xEF; 0x21]);
+        let (ticket_offset, ticket_size) = ticket_location(&bytes);
+        bytes[ticket_offset + ticket_size + 2] = 0xFF;
+
+        let footer_end = bytes.len() - 0x1F;
+        bytes[footer_end] = 0xFF;
+
+        assert_eq!(
+            verify(&bytes),
+            [
+                InstallableWadVerifyIssue::UnalignedSection {
+                    section: InstallableWadSection::Ticket,
+                    offset: (ticket_offset + ticket_size + 2) as u64,
+                },
+                InstallableWadVerifyIssue::UnalignedSection {
+                    section: InstallableWadSection::Footer,
+                    offset: footer_end as u64,
+                },
+            ]
+        );
+    }
+}
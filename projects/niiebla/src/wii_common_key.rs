@@ -11,8 +11,28 @@ use std::io;
 use std::io::Write;
 use thiserror::Error;
 
+/// The common key used on the Nintendo DSi to encrypt the title keys.
+pub const DSI_COMMON_KEY: [u8; 16] = [
+    0xaf, 0x1b, 0xf5, 0x16, 0xa8, 0x07, 0xd2, 0x1a, 0xea, 0x45, 0x98, 0x4f, 0x04, 0x74, 0x28, 0x61,
+];
+
+/// Constant used by the hardware key scrambler of the Nintendo 3DS.
+const KEY_SCRAMBLER_3DS_CONSTANT: u128 = 0x1FF9E9AAC5FE0408024591DC5D52768A;
+
+/// Derive the "normal key" of an AES keyslot of the Nintendo 3DS from its KeyX and KeyY, as done
+/// by its hardware key scrambler.
+pub const fn key_scrambler_3ds(key_x: [u8; 16], key_y: [u8; 16]) -> [u8; 16] {
+    let key_x = u128::from_be_bytes(key_x);
+    let key_y = u128::from_be_bytes(key_y);
+
+    (key_x.rotate_left(2) ^ key_y)
+        .wrapping_add(KEY_SCRAMBLER_3DS_CONSTANT)
+        .rotate_left(87)
+        .to_be_bytes()
+}
+
 /// Kinds of encryption keys used on the Nintendo Wii.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum WiiCommonKeyKind {
     /// Key used in most retail consoles.
     Normal,
@@ -43,18 +63,52 @@ impl WiiCommonKeyKind {
         })
     }
 
-    /// Get the identifier associated with the given common key.
-    pub fn dump_identifier<T: Write>(&self, mut stream: T) -> io::Result<()> {
-        stream.write_u8(match self {
+    /// Get the "common key index" (identifier) of the given common key.
+    pub const fn identifier(&self) -> u8 {
+        match self {
             Self::Normal => 0,
             Self::Korean => 1,
             Self::WiiUvWii => 2,
-        })?;
+        }
+    }
+
+    /// Get the identifier associated with the given common key.
+    pub fn dump_identifier<T: Write>(&self, mut stream: T) -> io::Result<()> {
+        stream.write_u8(self.identifier())?;
 
         Ok(())
     }
 
-    /// Get the bytes of the correct kind of common key.
+    /// Get the bytes of the correct kind of common key, given if it's for a development
+    /// console or for a retail one.
+    pub const fn bytes_for(&self, is_dev: bool) -> [u8; 16] {
+        if is_dev {
+            self.development_bytes()
+        } else {
+            self.bytes()
+        }
+    }
+
+    /// Get the bytes of the correct kind of common key used on development consoles (NDEV,
+    /// RVT-R, etc).
+    pub const fn development_bytes(&self) -> [u8; 16] {
+        match self {
+            Self::Normal => [
+                0xa1, 0x60, 0x4a, 0x6a, 0x71, 0x23, 0xb5, 0x29, 0xae, 0x8b, 0xec, 0x32, 0xc8, 0x16,
+                0xfc, 0xaa,
+            ],
+            Self::Korean => [
+                0x67, 0x45, 0x8b, 0x6b, 0xc6, 0x23, 0x7b, 0x32, 0x69, 0x98, 0x3c, 0x64, 0x73, 0x48,
+                0x33, 0x66,
+            ],
+            Self::WiiUvWii => [
+                0x2f, 0x5c, 0x1b, 0x29, 0x44, 0xe7, 0xfd, 0x6f, 0xc3, 0x97, 0x96, 0x4b, 0x05, 0x76,
+                0x91, 0xfa,
+            ],
+        }
+    }
+
+    /// Get the bytes of the correct kind of common key used on retail consoles.
     pub const fn bytes(&self) -> [u8; 16] {
         match self {
             Self::Normal => [
@@ -72,3 +126,40 @@ impl WiiCommonKeyKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_scrambler_3ds_zeroed_keys() {
+        // Only the constant, rotated
+        assert_eq!(
+            key_scrambler_3ds([0; 16], [0; 16]),
+            [
+                0xEE, 0x2E, 0xA9, 0x3B, 0x45, 0x0F, 0xFC, 0xF4, 0xD5, 0x62, 0xFF, 0x02, 0x04, 0x01,
+                0x22, 0xC8
+            ]
+        );
+    }
+
+    #[test]
+    fn key_scrambler_3ds_known_answer() {
+        let key_x = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
+            0x0E, 0x0F,
+        ];
+        let key_y = [
+            0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD,
+            0xFE, 0xFF,
+        ];
+
+        assert_eq!(
+            key_scrambler_3ds(key_x, key_y),
+            [
+                0x5A, 0x15, 0x0E, 0x1E, 0xA6, 0x88, 0x77, 0xF2, 0x55, 0x55, 0x6F, 0xF9, 0x79, 0xED,
+                0x91, 0xB2
+            ]
+        );
+    }
+}
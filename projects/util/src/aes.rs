@@ -10,6 +10,7 @@ use std::io::{Read, Seek, SeekFrom, Write};
 
 /// Decryptor of AES-128 encrypted bytes.
 pub type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+/// Encryptor of AES-128 bytes.
 pub type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
 
 /// Stream of AES-128 encrypted bytes.
@@ -102,6 +103,19 @@ impl<T: Write + Seek> AesCbcStream<T> {
 
         self.stream.write(&encrypted_buffer)
     }
+
+    /// Like [Self::write] but all the encrypted bytes are written, the same as
+    /// [std::io::Write::write_all].
+    pub fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut encrypted_buffer = vec![0; buf.len()];
+
+        self.encryptor
+            .clone()
+            .encrypt_padded_b2b_mut::<NoPadding>(buf, &mut encrypted_buffer)
+            .map_err(|err| io::Error::other(format!("Unable to encrypt the buffer: {err}")))?;
+
+        self.stream.write_all(&encrypted_buffer)
+    }
 }
 
 impl<T: Seek> Seek for AesCbcStream<T> {
@@ -73,6 +73,59 @@ impl<T: Write + Seek> StreamPin<T> {
     }
 }
 
+impl<T: Read + Write + Seek> StreamPin<T> {
+    const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+    /// Copy a range of bytes of the stream into another position of the same stream, both
+    /// positions relative to the pinned position. The ranges may overlap, the copy is done in
+    /// chunks with a bounded buffer so memory usage does not depend on the size of the range.
+    ///
+    /// The stream will be left at the end of the destination range.
+    pub fn copy_within(&mut self, source: u64, destination: u64, len: u64) -> io::Result<()> {
+        let mut buffer = vec![0; Self::COPY_BUFFER_SIZE];
+
+        self.copy_within_with_buffer(source, destination, len, &mut buffer)
+    }
+
+    fn copy_within_with_buffer(
+        &mut self,
+        source: u64,
+        destination: u64,
+        len: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<()> {
+        let source = self.start_position + source;
+        let destination = self.start_position + destination;
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let chunk_len = remaining.min(buffer.len() as u64);
+
+            // When moving the data forward the copy must start from the end to avoid overwriting
+            // bytes not yet copied, and the other way around
+            let offset = if destination > source {
+                remaining - chunk_len
+            } else {
+                len - remaining
+            };
+
+            let chunk = &mut buffer[..chunk_len as usize];
+
+            self.seek(SeekFrom::Start(source + offset))?;
+            self.read_exact(chunk)?;
+
+            self.seek(SeekFrom::Start(destination + offset))?;
+            self.write_all(chunk)?;
+
+            remaining -= chunk_len;
+        }
+
+        self.seek(SeekFrom::Start(destination + len))?;
+
+        Ok(())
+    }
+}
+
 impl<T: Seek> Seek for StreamPin<T> {
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
         self.stream.seek(pos)
@@ -188,4 +241,44 @@ mod tests {
         pin.seek_from_pin(-3).unwrap();
         assert_eq!(pin.read_u8().unwrap(), 2);
     }
+
+    #[test]
+    fn copy_within_forward_overlapping() {
+        let mut stream = Cursor::new(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 0, 0]);
+        stream.seek_relative(1).unwrap();
+
+        let mut pin = StreamPin::new(stream).unwrap();
+        pin.copy_within_with_buffer(1, 4, 8, &mut [0; 3]).unwrap();
+
+        assert_eq!(pin.stream_position().unwrap(), 13);
+        assert_eq!(
+            pin.into_inner().into_inner(),
+            [0, 1, 2, 3, 4, 2, 3, 4, 5, 6, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn copy_within_backward_overlapping() {
+        let mut stream = Cursor::new(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        stream.seek_relative(1).unwrap();
+
+        let mut pin = StreamPin::new(stream).unwrap();
+        pin.copy_within_with_buffer(4, 1, 5, &mut [0; 2]).unwrap();
+
+        assert_eq!(pin.stream_position().unwrap(), 7);
+        assert_eq!(
+            pin.into_inner().into_inner(),
+            [0, 1, 5, 6, 7, 8, 9, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn copy_within_past_the_end() {
+        let mut stream = Cursor::new(vec![0, 1, 2, 3]);
+
+        let mut pin = StreamPin::new(&mut stream).unwrap();
+        pin.copy_within(1, 4, 3).unwrap();
+
+        assert_eq!(stream.into_inner(), [0, 1, 2, 3, 1, 2, 3]);
+    }
 }
@@ -6,7 +6,7 @@
 
 //! TODO
 
-use niiebla::{CryptographicMethod, TitleMetadataContentEntryKind, Wad};
+use niiebla::{CryptographicMethod, EmbeddedKeyProvider, TitleMetadataContentEntryKind, Wad};
 use std::fs::File;
 use std::io;
 use std::io::{Cursor, Seek};
@@ -54,7 +54,7 @@ fn main() {
     new_wad_file.rewind().unwrap();
 
     wad.modify_content(&mut new_wad_file)
-        .set_cryptography(&ticket, CryptographicMethod::Wii)
+        .set_cryptography(&ticket, CryptographicMethod::Wii, &EmbeddedKeyProvider)
         .trim_if_file(true)
         .set_id(666)
         .set_index(444)
@@ -63,13 +63,13 @@ fn main() {
         .unwrap();
 
     wad.modify_content(&mut new_wad_file)
-        .set_cryptography(&ticket, CryptographicMethod::Wii)
+        .set_cryptography(&ticket, CryptographicMethod::Wii, &EmbeddedKeyProvider)
         .trim_if_file(true)
         .remove(tmd.select_with_physical_position(2), &mut tmd)
         .unwrap();
 
     wad.modify_content(&mut new_wad_file)
-        .set_cryptography(&ticket, CryptographicMethod::Wii)
+        .set_cryptography(&ticket, CryptographicMethod::Wii, &EmbeddedKeyProvider)
         .trim_if_file(true)
         .set_id(222)
         .set_index(333)
@@ -92,6 +92,7 @@ fn main() {
             &ticket,
             &tmd,
             CryptographicMethod::Wii,
+            &EmbeddedKeyProvider,
             tmd.select_with_id(222),
         )
         .unwrap();